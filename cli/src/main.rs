@@ -0,0 +1,477 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use lib::models::{TitleId, WatchStatus};
+use lib::{ImportFormat, ImportReport, Library, Outcome, SortKey, Title, TitleFilter};
+use pico_args::Arguments;
+use serde_json::json;
+
+const USAGE: &str = "\
+Manages the meepls library without the window. Close meepls first, whichever
+of the two saves last wins.
+
+Usage: meepls-cli [--json] <command> [options]
+
+Commands:
+  add <tt-id|name> [--year YEAR] [--status STATUS]
+      Adds a title by IMDb id, or by name if it leaves no doubt. Names that
+      start with - go after --
+  import <file> [--format imdb|letterboxd|trakt]
+      Imports an export, failing if it isn't in the format given
+  export [--format json|imdb] [--output FILE]
+      Writes the library as meepls JSON or an IMDb CSV, to stdout by default
+  list [--filter NAME=VALUE]... [--status STATUS] [--sort KEY] [--desc]
+      Lists the titles. Filters: search, type (movie|series), genre,
+      year (1990 or 1990..1999), rating (TMDB's, 7..), rated, unrated,
+      my-rating (7..10), person.
+      Sort keys: title, year, rating, my-rating, added
+  rate <tt-id> <1-10|clear>
+      Sets or clears your rating, in steps of 0.5
+  refresh [<tt-id>...] [--all]
+      Fetches metadata again, of the stale titles if none are given, and
+      adds the titles queued while offline
+  recommend [--limit N] [--find-new]
+      Lists recommendations, looking for new candidates on TMDB first with
+      --find-new
+
+Statuses: watchlist, watching, watched, dropped
+
+Options:
+  --json      Print JSON instead of a table
+  -h, --help  Print this help
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Table,
+    Json,
+}
+
+fn parse_status(s: &str) -> Result<WatchStatus, String> {
+    WatchStatus::ALL
+        .into_iter()
+        .find(|status| status.to_string().eq_ignore_ascii_case(s))
+        .ok_or_else(|| format!("unknown status \"{s}\""))
+}
+
+fn parse_id(s: &str) -> Result<TitleId, String> {
+    s.parse()
+        .map_err(|_| format!("\"{s}\" isn't an IMDb id like tt0110413"))
+}
+
+fn parse_import_format(s: &str) -> Result<&'static [ImportFormat], String> {
+    match s {
+        "imdb" => Ok(&[ImportFormat::Imdb, ImportFormat::ImdbWatchlist]),
+        "letterboxd" => Ok(&[ImportFormat::Letterboxd]),
+        "trakt" => Ok(&[ImportFormat::Trakt]),
+        _ => Err(format!("unknown import format \"{s}\"")),
+    }
+}
+
+fn parse_rating(s: &str) -> Result<Option<f32>, String> {
+    match s {
+        "clear" => Ok(None),
+        _ => s
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("\"{s}\" isn't a rating")),
+    }
+}
+
+/// Fails on arguments no option took.
+fn no_more(args: Arguments) -> Result<(), String> {
+    let rest = args.finish();
+    if rest.is_empty() {
+        return Ok(());
+    }
+    let rest: Vec<String> = rest
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    Err(format!("unexpected arguments: {}", rest.join(" ")))
+}
+
+/// The free arguments left, e.g. the words of a name. Fails on options no
+/// option took, like a misspelt `--status`, unless they follow `--`.
+fn free_words(args: Arguments) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut rest = args.finish().into_iter();
+    for arg in rest.by_ref() {
+        let arg = arg.to_string_lossy().into_owned();
+        if arg == "--" {
+            break;
+        }
+        if arg.starts_with('-') {
+            return Err(format!("unknown option \"{arg}\""));
+        }
+        words.push(arg);
+    }
+    words.extend(rest.map(|arg| arg.to_string_lossy().into_owned()));
+    Ok(words)
+}
+
+/// What `add` was asked to add: the id or name, the year and the status.
+fn parse_add(mut args: Arguments) -> Result<(String, Option<u16>, WatchStatus), Box<dyn Error>> {
+    let year = args.opt_value_from_str("--year")?;
+    let status = args
+        .opt_value_from_fn("--status", parse_status)?
+        .unwrap_or_default();
+    let words = free_words(args)?;
+    if words.is_empty() {
+        return Err("add needs an IMDb id or a name".into());
+    }
+    Ok((words.join(" "), year, status))
+}
+
+/// Prints `rows` in columns under `header`.
+fn print_table<const N: usize>(
+    out: &mut impl Write,
+    header: [&str; N],
+    rows: &[[String; N]],
+) -> io::Result<()> {
+    let mut widths = header.map(|h| h.chars().count());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut print_row = |cells: [&str; N]| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        writeln!(out, "{}", line.join("  ").trim_end())
+    };
+    print_row(header)?;
+    for row in rows {
+        print_row(row.each_ref().map(String::as_str))?;
+    }
+    Ok(())
+}
+
+fn print_json(out: &mut impl Write, value: &impl serde::Serialize) -> Result<(), Box<dyn Error>> {
+    writeln!(out, "{}", serde_json::to_string_pretty(value)?)?;
+    Ok(())
+}
+
+fn title_row(title: &Title) -> [String; 7] {
+    [
+        title.id().to_string(),
+        title.title().to_owned(),
+        match title.year() {
+            0 => String::new(),
+            year => year.to_string(),
+        },
+        title.ty().to_string(),
+        title.status().to_string(),
+        title.my_rating().map(|r| r.to_string()).unwrap_or_default(),
+        format!("{:.1}", title.rating()),
+    ]
+}
+
+fn print_titles(
+    out: &mut impl Write,
+    titles: &[&Title],
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    match output {
+        Output::Json => print_json(out, &titles)?,
+        Output::Table => {
+            let rows: Vec<_> = titles.iter().map(|t| title_row(t)).collect();
+            let header = ["ID", "Title", "Year", "Type", "Status", "Mine", "TMDB"];
+            print_table(out, header, &rows)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints what became of every record, failing if any couldn't be added.
+fn print_report(
+    out: &mut impl Write,
+    report: &ImportReport,
+    output: Output,
+) -> Result<ExitCode, Box<dyn Error>> {
+    match output {
+        Output::Json => {
+            let rows: Vec<_> = report
+                .rows
+                .iter()
+                .map(|row| {
+                    json!({
+                        "file": row.origin.file,
+                        "line": row.origin.line,
+                        "title": row.origin.label,
+                        "result": row.outcome.name(),
+                        "reason": row.reason(),
+                    })
+                })
+                .collect();
+            print_json(out, &rows)?;
+        }
+        Output::Table => {
+            let rows: Vec<_> = report
+                .rows
+                .iter()
+                .map(|row| {
+                    [
+                        row.origin.label.clone(),
+                        row.outcome.name().to_owned(),
+                        row.reason(),
+                    ]
+                })
+                .collect();
+            print_table(out, ["Title", "Result", "Reason"], &rows)?;
+        }
+    }
+    let failed = report.count(|o| matches!(o, Outcome::UnknownType(_) | Outcome::Failed(_)));
+    Ok(if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn run(mut args: Arguments, output: Output) -> Result<ExitCode, Box<dyn Error>> {
+    let Some(command) = args.subcommand()? else {
+        return Err("no command given, see --help".into());
+    };
+    let mut library = Library::open()?;
+    if let Some(err) = library.take_warning() {
+        eprintln!("meepls-cli: starting with default settings: {err}");
+    }
+    let out = &mut io::stdout().lock();
+    match command.as_str() {
+        "add" => {
+            let (input, year, status) = parse_add(args)?;
+            let report = library.add(&input, year, status);
+            library.save()?;
+            print_report(out, &report, output)
+        }
+        "import" => {
+            let formats = args.opt_value_from_fn("--format", parse_import_format)?;
+            let path: PathBuf = args.free_from_str()?;
+            no_more(args)?;
+            let report = library.import(&path, formats)?;
+            library.save()?;
+            print_report(out, &report, output)
+        }
+        "export" => {
+            let format: Option<String> = args.opt_value_from_str("--format")?;
+            let path: Option<PathBuf> = args.opt_value_from_str("--output")?;
+            no_more(args)?;
+            let writer: Box<dyn Write> = match path {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(out),
+            };
+            match format.as_deref().unwrap_or("json") {
+                "json" => library.write_json(writer)?,
+                "imdb" => library.write_imdb_csv(writer)?,
+                format => return Err(format!("unknown export format \"{format}\"").into()),
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        "list" => {
+            let mut filter = TitleFilter::default();
+            for condition in args.values_from_str::<_, String>("--filter")? {
+                filter.add_condition(&condition)?;
+            }
+            if let Some(sort) = args.opt_value_from_str::<_, SortKey>("--sort")? {
+                filter.sort = sort;
+            }
+            filter.descending = args.contains("--desc");
+            let status = args.opt_value_from_fn("--status", parse_status)?;
+            no_more(args)?;
+            print_titles(out, &library.titles(&filter, status), output)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        "rate" => {
+            let id = args.free_from_fn(parse_id)?;
+            let rating = args.free_from_fn(parse_rating)?;
+            no_more(args)?;
+            let title = library.rate(id, rating)?.clone();
+            library.save()?;
+            print_titles(out, &[&title], output)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        "refresh" => {
+            let all = args.contains("--all");
+            let mut ids: Vec<TitleId> = Vec::new();
+            while let Some(id) = args.opt_free_from_fn(parse_id)? {
+                ids.push(id);
+            }
+            no_more(args)?;
+            if all {
+                ids = library
+                    .titles(&TitleFilter::default(), None)
+                    .iter()
+                    .map(|t| t.id())
+                    .collect();
+            } else if ids.is_empty() {
+                ids = library.stale();
+            }
+            let mut report = if library.queued() > 0 {
+                library.add_queued()?
+            } else {
+                ImportReport::default()
+            };
+            report.rows.extend(library.refresh(ids)?.rows);
+            library.save()?;
+            print_report(out, &report, output)
+        }
+        "recommend" => {
+            let limit = args.opt_value_from_str("--limit")?.unwrap_or(10);
+            let find_new = args.contains("--find-new");
+            no_more(args)?;
+            let recommended = library.recommend(limit, find_new)?;
+            match output {
+                Output::Json => {
+                    let recommended: Vec<_> = recommended
+                        .iter()
+                        .map(|(title, rec)| {
+                            let factors: Vec<_> = rec
+                                .factors()
+                                .iter()
+                                .map(
+                                    |f| json!({ "factor": f.kind.to_string(), "weight": f.weight }),
+                                )
+                                .collect();
+                            json!({
+                                "title": title,
+                                "score": rec.score(),
+                                "summary": rec.summary(),
+                                "factors": factors,
+                            })
+                        })
+                        .collect();
+                    print_json(out, &recommended)?;
+                }
+                Output::Table => {
+                    let rows: Vec<_> = recommended
+                        .iter()
+                        .map(|(title, rec)| {
+                            let [id, name, year, ty, ..] = title_row(title);
+                            let score = format!("{:.0}%", rec.score().max(0.0) * 100.0);
+                            [id, name, year, ty, score, rec.summary().to_owned()]
+                        })
+                        .collect();
+                    let header = ["ID", "Title", "Year", "Type", "Match", "Why"];
+                    print_table(out, header, &rows)?;
+                }
+            }
+            if find_new {
+                library.save()?;
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        _ => Err(format!("unknown command \"{command}\", see --help").into()),
+    }
+}
+
+/// Whether `err` comes down to the reader of the output going away, e.g.
+/// `head` having read enough.
+fn is_broken_pipe(err: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            return err.kind() == io::ErrorKind::BrokenPipe;
+        }
+        source = err.source();
+    }
+    false
+}
+
+fn main() -> ExitCode {
+    let mut args = Arguments::from_env();
+    if args.contains(["-h", "--help"]) {
+        print!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    let output = if args.contains("--json") {
+        Output::Json
+    } else {
+        Output::Table
+    };
+    match run(args, output) {
+        Ok(code) => code,
+        Err(err) if is_broken_pipe(err.as_ref()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("meepls-cli: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Arguments {
+        Arguments::from_vec(args.iter().map(Into::into).collect())
+    }
+
+    #[test]
+    fn reads_add_arguments() {
+        let (input, year, status) = parse_add(args(&[
+            "Mr.",
+            "Nobody",
+            "--year",
+            "2009",
+            "--status",
+            "watchlist",
+        ]))
+        .unwrap();
+        assert_eq!(input, "Mr. Nobody");
+        assert_eq!(year, Some(2009));
+        assert_eq!(status, WatchStatus::Watchlist);
+
+        let (input, year, status) = parse_add(args(&["tt0110413"])).unwrap();
+        assert_eq!(input, "tt0110413");
+        assert_eq!(year, None);
+        assert_eq!(status, WatchStatus::default());
+
+        let (input, ..) = parse_add(args(&["--", "-1", "Day"])).unwrap();
+        assert_eq!(input, "-1 Day");
+    }
+
+    #[test]
+    fn rejects_bad_add_arguments() {
+        let error = |list: &[&str]| parse_add(args(list)).unwrap_err().to_string();
+        assert_eq!(
+            error(&["Heat", "--stauts", "watched"]),
+            "unknown option \"--stauts\""
+        );
+        assert_eq!(error(&["--year", "1995"]), "add needs an IMDb id or a name");
+        assert_eq!(
+            error(&["Heat", "--status", "seen"]),
+            "failed to parse 'seen': unknown status \"seen\""
+        );
+        assert!(parse_add(args(&["Heat", "--year", "ninety"])).is_err());
+    }
+
+    #[test]
+    fn fails_on_leftover_arguments() {
+        assert!(no_more(args(&[])).is_ok());
+        assert_eq!(
+            no_more(args(&["ratings.csv", "--fromat"])).unwrap_err(),
+            "unexpected arguments: ratings.csv --fromat"
+        );
+    }
+
+    #[test]
+    fn reads_values() {
+        assert_eq!(parse_rating("7.5"), Ok(Some(7.5)));
+        assert_eq!(parse_rating("clear"), Ok(None));
+        assert!(parse_rating("great").is_err());
+        assert_eq!(parse_id("tt0110413"), Ok(TitleId(110413)));
+        assert!(parse_id("110413").is_err());
+        assert_eq!(parse_status("Watching"), Ok(WatchStatus::Watching));
+        assert_eq!(parse_import_format("trakt"), Ok(&[ImportFormat::Trakt][..]));
+        assert!(parse_import_format("csv").is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use egui::load::{Bytes, BytesLoadResult, BytesLoader, BytesPoll, LoadError};
+use sha2::{Digest, Sha256};
+
+use crate::settings::{Settings, TMDB_IMG_BASE};
+
+/// How long a TMDB response is used before asking again.
+pub const API_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Lowercase hex SHA-256 of `bytes`.
+pub(crate) fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, b| {
+            let _ = write!(hex, "{b:02x}");
+            hex
+        })
+}
+
+/// TMDB responses and posters kept on disk between launches.
+///
+/// Responses are stored under `api/` by base URL and path, with a file per
+/// hashed query. Posters are stored once per content under `posters/blobs/`, with a file
+/// per URL under `posters/urls/` naming the blob.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+impl DiskCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The cache in the app's data directory, if the platform has one.
+    pub fn in_data_dir() -> Option<Self> {
+        eframe::storage_dir(crate::APP_ID).map(|dir| Self::new(dir.join("cache")))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The response stored at `key`, unless it is older than `max_age`.
+    pub(crate) fn response(&self, key: &Path, max_age: Option<Duration>) -> Option<Vec<u8>> {
+        let file = self.dir.join("api").join(key);
+        if let Some(max_age) = max_age {
+            let modified = fs::metadata(&file).and_then(|m| m.modified()).ok()?;
+            let age = SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default();
+            if age > max_age {
+                return None;
+            }
+        }
+        fs::read(file).ok()
+    }
+
+    pub(crate) fn store_response(&self, key: &Path, bytes: &[u8]) -> io::Result<()> {
+        write_file(&self.dir.join("api").join(key), bytes)
+    }
+
+    fn url_file(&self, url: &str) -> PathBuf {
+        self.dir
+            .join("posters")
+            .join("urls")
+            .join(sha256(url.as_bytes()))
+    }
+
+    fn blob_file(&self, hash: &str) -> PathBuf {
+        self.dir.join("posters").join("blobs").join(hash)
+    }
+
+    pub(crate) fn poster(&self, url: &str) -> Option<Vec<u8>> {
+        let hash = fs::read_to_string(self.url_file(url)).ok()?;
+        fs::read(self.blob_file(hash.trim())).ok()
+    }
+
+    pub(crate) fn store_poster(&self, url: &str, bytes: &[u8]) -> io::Result<()> {
+        let hash = sha256(bytes);
+        let blob = self.blob_file(&hash);
+        if !blob.exists() {
+            write_file(&blob, bytes)?;
+        }
+        write_file(&self.url_file(url), hash.as_bytes())
+    }
+
+    /// Total size of the cached files in bytes.
+    pub fn size(&self) -> u64 {
+        fn dir_size(dir: &Path) -> u64 {
+            let Ok(entries) = fs::read_dir(dir) else {
+                return 0;
+            };
+            entries
+                .flatten()
+                .map(|entry| match entry.file_type() {
+                    Ok(ty) if ty.is_dir() => dir_size(&entry.path()),
+                    _ => entry.metadata().map(|m| m.len()).unwrap_or_default(),
+                })
+                .sum()
+        }
+        dir_size(&self.dir)
+    }
+
+    pub fn clear(&self) -> io::Result<()> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Writes through a temporary file so readers never see half a file.
+fn write_file(file: &Path, bytes: &[u8]) -> io::Result<()> {
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = file.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp, bytes)?;
+    fs::rename(tmp, file)
+}
+
+/// How long a poster that failed to load is left alone before trying again.
+const POSTER_RETRY: Duration = Duration::from_secs(30);
+
+enum Poster {
+    Loading,
+    Loaded(Arc<[u8]>),
+    Failed { err: String, at: Instant },
+}
+
+/// Loads TMDB posters from the [`DiskCache`], downloading and storing the
+/// ones it doesn't have yet unless offline. Failed posters are tried again
+/// after a while. Other URLs are left to the other loaders.
+pub struct PosterLoader {
+    cache: DiskCache,
+    settings: Arc<Mutex<Settings>>,
+    loaded: Arc<Mutex<HashMap<String, Poster>>>,
+    retry_after: Duration,
+}
+
+impl PosterLoader {
+    pub const ID: &'static str = egui::generate_loader_id!(PosterLoader);
+
+    pub fn new(cache: DiskCache, settings: Arc<Mutex<Settings>>) -> Self {
+        Self {
+            cache,
+            settings,
+            loaded: Arc::default(),
+            retry_after: POSTER_RETRY,
+        }
+    }
+}
+
+impl BytesLoader for PosterLoader {
+    fn id(&self) -> &str {
+        Self::ID
+    }
+
+    fn load(&self, ctx: &egui::Context, uri: &str) -> BytesLoadResult {
+        if !uri.starts_with(TMDB_IMG_BASE) {
+            return Err(LoadError::NotSupported);
+        }
+        let mut loaded = self.loaded.lock().unwrap();
+        match loaded.get(uri) {
+            Some(Poster::Loaded(bytes)) => {
+                return Ok(BytesPoll::Ready {
+                    size: None,
+                    bytes: Bytes::Shared(Arc::clone(bytes)),
+                    mime: None,
+                })
+            }
+            Some(Poster::Failed { err, at }) if at.elapsed() < self.retry_after => {
+                return Err(LoadError::Loading(err.clone()))
+            }
+            Some(Poster::Loading) => return Ok(BytesPoll::Pending { size: None }),
+            Some(Poster::Failed { .. }) | None => {}
+        }
+        loaded.insert(uri.to_owned(), Poster::Loading);
+        drop(loaded);
+
+        let uri = uri.to_owned();
+        let cache = self.cache.clone();
+        let loaded = Arc::clone(&self.loaded);
+        let settings = Arc::clone(&self.settings);
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let key = uri.clone();
+            let done = move |poster: Result<Vec<u8>, String>| {
+                let poster = match poster {
+                    Ok(bytes) => Poster::Loaded(bytes.into()),
+                    Err(err) => Poster::Failed {
+                        err,
+                        at: Instant::now(),
+                    },
+                };
+                loaded.lock().unwrap().insert(key, poster);
+                ctx.request_repaint();
+            };
+            if let Some(bytes) = cache.poster(&uri) {
+                return done(Ok(bytes));
+            }
+            if settings.lock().unwrap().is_offline() {
+                return done(Err(format!("{uri} isn't cached")));
+            }
+            ehttp::fetch(ehttp::Request::get(&uri), move |response| {
+                let poster = match response {
+                    Ok(response) if response.ok => {
+                        // A poster that can't be cached is still worth showing.
+                        let _ = cache.store_poster(&uri, &response.bytes);
+                        Ok(response.bytes)
+                    }
+                    Ok(response) => Err(format!(
+                        "failed to load {uri}: {} {}",
+                        response.status, response.status_text
+                    )),
+                    Err(err) => {
+                        settings.lock().unwrap().unreachable = true;
+                        Err(format!("failed to load {uri}: {err}"))
+                    }
+                };
+                done(poster);
+            });
+        });
+        Ok(BytesPoll::Pending { size: None })
+    }
+
+    fn forget(&self, uri: &str) {
+        self.loaded.lock().unwrap().remove(uri);
+    }
+
+    fn forget_all(&self) {
+        self.loaded.lock().unwrap().clear();
+    }
+
+    fn byte_size(&self) -> usize {
+        self.loaded
+            .lock()
+            .unwrap()
+            .values()
+            .map(|poster| match poster {
+                Poster::Loaded(bytes) => bytes.len(),
+                Poster::Failed { err, .. } => err.len(),
+                Poster::Loading => 0,
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_cache(name: &str) -> DiskCache {
+        let dir = std::env::temp_dir().join(format!("meepls-{name}-{}", std::process::id()));
+        DiskCache::new(dir)
+    }
+
+    #[test]
+    fn responses_expire() {
+        let cache = temp_cache("cache-responses");
+        let key = Path::new("api.themoviedb.org").join("movie/949.json");
+        assert_eq!(cache.response(&key, None), None);
+        cache.store_response(&key, b"{}").unwrap();
+        assert_eq!(cache.response(&key, Some(API_TTL)), Some(b"{}".to_vec()));
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(cache.response(&key, Some(Duration::from_millis(10))), None);
+        // Stale responses are still there for when TMDB can't be reached.
+        assert_eq!(cache.response(&key, None), Some(b"{}".to_vec()));
+        cache.clear().unwrap();
+        assert_eq!(cache.size(), 0);
+    }
+
+    #[test]
+    fn posters_are_stored_once() {
+        let cache = temp_cache("cache-posters");
+        let small = format!("{TMDB_IMG_BASE}w92/heat.jpg");
+        let large = format!("{TMDB_IMG_BASE}w500/heat.jpg");
+        cache.store_poster(&small, b"poster").unwrap();
+        cache.store_poster(&large, b"poster").unwrap();
+        assert_eq!(cache.poster(&small), Some(b"poster".to_vec()));
+        assert_eq!(cache.poster(&large), Some(b"poster".to_vec()));
+        let blobs = fs::read_dir(cache.dir().join("posters").join("blobs")).unwrap();
+        assert_eq!(blobs.count(), 1);
+        cache.clear().unwrap();
+    }
+
+    /// Polls `loader` until it's done with `uri`.
+    fn load(loader: &PosterLoader, ctx: &egui::Context, uri: &str) -> BytesLoadResult {
+        for _ in 0..500 {
+            match loader.load(ctx, uri) {
+                Ok(BytesPoll::Pending { .. }) => thread::sleep(Duration::from_millis(10)),
+                result => return result,
+            }
+        }
+        panic!("{uri} is still loading");
+    }
+
+    #[test]
+    fn failed_posters_are_tried_again() {
+        let cache = temp_cache("cache-loader");
+        let settings = Settings {
+            work_offline: true,
+            ..Default::default()
+        };
+        let mut loader = PosterLoader::new(cache.clone(), Arc::new(Mutex::new(settings)));
+        loader.retry_after = Duration::from_secs(3600);
+        let ctx = egui::Context::default();
+        let uri = format!("{TMDB_IMG_BASE}w92/heat.jpg");
+        assert!(matches!(
+            load(&loader, &ctx, &uri),
+            Err(LoadError::Loading(_))
+        ));
+
+        cache.store_poster(&uri, b"poster").unwrap();
+        assert!(matches!(
+            loader.load(&ctx, &uri),
+            Err(LoadError::Loading(_))
+        ));
+        loader.retry_after = Duration::ZERO;
+        match load(&loader, &ctx, &uri) {
+            Ok(BytesPoll::Ready { bytes, .. }) => assert_eq!(bytes.as_ref(), b"poster"),
+            Ok(_) => panic!("expected the cached poster"),
+            Err(err) => panic!("expected the cached poster, got {err}"),
+        }
+        cache.clear().unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::models::TitleType;
+use crate::my_titles::{Title, TitleList, Titles};
+
+/// Bumped whenever [`LibraryExport`] changes in a way older versions can't read.
+pub const EXPORT_VERSION: u32 = 1;
+
+/// The whole library, as written to and read back from JSON.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LibraryExport {
+    pub version: u32,
+    pub titles: Vec<Title>,
+    #[serde(default)]
+    pub lists: Vec<TitleList>,
+}
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("couldn't write the file: {0}")]
+    Io(#[from] io::Error),
+    #[error("couldn't write the CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("couldn't write the JSON: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+fn imdb_title_type(ty: TitleType) -> &'static str {
+    match ty {
+        TitleType::Movie => "movie",
+        TitleType::Series => "tvSeries",
+    }
+}
+
+/// Writes the titles in the columns of IMDb's ratings export, so it can be
+/// read back by anything that reads those. IMDb rates in whole steps, so half
+/// steps are rounded up, the JSON export keeps them. Tags, notes and lists
+/// follow in columns of their own.
+pub fn write_imdb_csv(
+    titles: &Titles,
+    lists: &[TitleList],
+    path: &Path,
+) -> Result<(), ExportError> {
+    write_imdb_csv_to(titles, lists, File::create(path)?)
+}
+
+/// [`write_imdb_csv`] to any writer, e.g. stdout.
+pub fn write_imdb_csv_to(
+    titles: &Titles,
+    lists: &[TitleList],
+    writer: impl Write,
+) -> Result<(), ExportError> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record([
+        "Const",
+        "Your Rating",
+        "Title",
+        "Year",
+        "Title Type",
+        "Genres",
+        "Directors",
+        "Tags",
+        "Notes",
+        "Lists",
+    ])?;
+    for title in titles.values() {
+        let in_lists: Vec<&str> = lists
+            .iter()
+            .filter(|list| list.titles.contains(&title.id))
+            .map(|list| list.name.as_str())
+            .collect();
+        wtr.write_record([
+            title.id.to_string(),
+            title
+                .my_rating
+                .map(|r| r.round().to_string())
+                .unwrap_or_default(),
+            title.title.clone(),
+            match title.year {
+                0 => String::new(),
+                year => year.to_string(),
+            },
+            imdb_title_type(title.ty).to_owned(),
+            title.genres.join(", "),
+            title.directors.join(", "),
+            title.tags.iter().cloned().collect::<Vec<_>>().join(", "),
+            title.notes.clone(),
+            in_lists.join(", "),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Writes every field of every title and the lists, to be imported back as is.
+pub fn write_json(titles: &Titles, lists: &[TitleList], path: &Path) -> Result<(), ExportError> {
+    write_json_to(titles, lists, File::create(path)?)
+}
+
+/// [`write_json`] to any writer, e.g. stdout.
+pub fn write_json_to(
+    titles: &Titles,
+    lists: &[TitleList],
+    writer: impl Write,
+) -> Result<(), ExportError> {
+    let export = LibraryExport {
+        version: EXPORT_VERSION,
+        titles: titles.values().cloned().collect(),
+        lists: lists.to_vec(),
+    };
+    let mut writer = BufWriter::new(writer);
+    serde_json::to_writer_pretty(&mut writer, &export)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, fs};
+
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::import::read_file;
+    use crate::models::WatchStatus;
+    use crate::my_titles::{import_lists, insert_title, RatingEdit};
+
+    fn library() -> (Titles, Vec<TitleList>) {
+        let mut heat = Title::test(113277, "Heat");
+        heat.year = 1995;
+        heat.rating = 8.3;
+        heat.my_rating = Some(7.5);
+        heat.rating_history = vec![RatingEdit {
+            at: Utc.with_ymd_and_hms(2023, 5, 1, 20, 0, 0).unwrap(),
+            rating: Some(7.5),
+        }];
+        heat.directors = vec!["Michael Mann".to_owned()];
+        heat.genres = vec!["Crime".to_owned(), "Thriller".to_owned()];
+        heat.tags = BTreeSet::from(["heist".to_owned()]);
+        heat.notes = "The diner scene.".to_owned();
+        heat.added_at = Some(Utc.with_ymd_and_hms(2023, 4, 30, 9, 15, 0).unwrap());
+        let mut bad = Title::test(903747, "Breaking Bad");
+        bad.ty = TitleType::Series;
+        bad.status = WatchStatus::Watchlist;
+        bad.tmdb_id = Some(1396);
+        bad.added_at = Some(Utc.with_ymd_and_hms(2023, 6, 1, 0, 0, 0).unwrap());
+        let lists = vec![TitleList {
+            name: "Favourites".to_owned(),
+            titles: vec![heat.id],
+        }];
+        let titles = [heat, bad].into_iter().map(|t| (t.id, t)).collect();
+        (titles, lists)
+    }
+
+    #[test]
+    fn json_export_imports_back_as_is() {
+        let (titles, lists) = library();
+        let dir = std::env::temp_dir().join(format!("meepls-export-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("library.json");
+        write_json(&titles, &lists, &path).unwrap();
+        let read = read_file(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut imported = Titles::new();
+        for (_, title) in read.titles {
+            insert_title(&mut imported, title);
+        }
+        let mut imported_lists = Vec::new();
+        import_lists(&mut imported_lists, read.lists);
+        assert_eq!(imported, titles);
+        assert_eq!(imported_lists, lists);
+        assert!(read.items.is_empty() && read.rejected.is_empty());
+    }
+
+    #[test]
+    fn imdb_csv_rates_in_whole_steps() {
+        let (titles, lists) = library();
+        let mut csv = Vec::new();
+        write_imdb_csv_to(&titles, &lists, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            rows,
+            [
+                "Const,Your Rating,Title,Year,Title Type,Genres,Directors,Tags,Notes,Lists",
+                "tt0113277,8,Heat,1995,movie,\"Crime, Thriller\",Michael Mann,heist,The diner scene.,Favourites",
+                "tt0903747,,Breaking Bad,,tvSeries,,,,,",
+            ]
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use chrono::Utc;
+
+use crate::models::{tmdb, TitleId, TitleType, WatchStatus};
+use crate::my_titles::Title;
+use crate::provider::{FetchError, MetadataProvider, Related};
+
+pub(crate) fn get_title_id_from_imdb(
+    provider: &dyn MetadataProvider,
+    id: TitleId,
+) -> Result<(i32, TitleType), FetchError> {
+    let result = provider.find_by_imdb(id)?;
+    match (&result.movie_results[..], &result.tv_results[..]) {
+        ([movie], []) => Ok((movie.id, TitleType::Movie)),
+        ([], [series]) => Ok((series.id, TitleType::Series)),
+        ([], []) => Err(FetchError::NotFound(id)),
+        _ => Err(FetchError::Ambiguous(id)),
+    }
+}
+
+pub(crate) fn get_title_from_tmdb(
+    provider: &dyn MetadataProvider,
+    id: i32,
+    typ: TitleType,
+    my_rating: Option<f32>,
+) -> Result<Title, FetchError> {
+    let details = provider.details(id, typ)?;
+    let imdb_id = details
+        .imdb_id()
+        .and_then(|id| id.parse().ok())
+        .ok_or(FetchError::MissingField("IMDb id"))?;
+    // Unreleased titles may not have a date yet.
+    let year = details
+        .release_date
+        .get(..4)
+        .and_then(|year| year.parse().ok())
+        .unwrap_or_default();
+    let directors = match typ {
+        TitleType::Movie => details
+            .credits
+            .crew
+            .into_iter()
+            .filter_map(|c| {
+                if c.job == "Director" {
+                    Some(c.name)
+                } else {
+                    None
+                }
+            })
+            .collect(),
+        TitleType::Series => details
+            .created_by
+            .unwrap_or_default()
+            .into_iter()
+            .map(|c| c.name)
+            .collect(),
+    };
+
+    let actors = details
+        .credits
+        .cast
+        .iter()
+        .take(3)
+        .map(|c| c.name.clone())
+        .collect();
+
+    let genres = details.genres.iter().map(|g| g.name.clone()).collect();
+
+    Ok(Title {
+        id: imdb_id,
+        title: details.title,
+        year,
+        ty: typ,
+        rating: details.rating,
+        my_rating,
+        directors,
+        actors,
+        genres,
+        poster_img: details.poster_path.unwrap_or_default(),
+        description: details.overview,
+        tmdb_id: Some(id),
+        watch_count: 0,
+        last_watched: None,
+        status: WatchStatus::default(),
+        rating_history: Vec::new(),
+        added_at: None,
+        notes: String::new(),
+        tags: Default::default(),
+        fetched_at: Some(Utc::now()),
+        fetched_from: provider.name().to_owned(),
+    })
+}
+
+/// Fetches the first page of titles TMDB considers `related` to `id`. The
+/// returned titles are of the same type as `id`.
+pub(crate) fn get_related_titles(
+    provider: &dyn MetadataProvider,
+    id: i32,
+    typ: TitleType,
+    related: Related,
+) -> Result<Vec<tmdb::RelatedTitle>, FetchError> {
+    Ok(provider.related(id, typ, related)?.results)
+}
+
+/// A movie or series matching a search.
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub id: i32,
+    pub ty: TitleType,
+    pub title: String,
+    pub year: Option<u16>,
+    pub poster_path: Option<String>,
+}
+
+/// Searches movies and series by name, people are left out.
+pub(crate) fn search_titles(
+    provider: &dyn MetadataProvider,
+    query: &str,
+) -> Result<Vec<SearchHit>, FetchError> {
+    Ok(provider
+        .search(query)?
+        .results
+        .into_iter()
+        .filter_map(|result| {
+            let ty = match result.media_type.as_str() {
+                "movie" => TitleType::Movie,
+                "tv" => TitleType::Series,
+                _ => return None,
+            };
+            Some(SearchHit {
+                id: result.id,
+                ty,
+                year: result
+                    .release_date
+                    .as_deref()
+                    .and_then(|date| date.get(..4)?.parse().ok()),
+                title: result.title,
+                poster_path: result.poster_path,
+            })
+        })
+        .collect())
+}
+
+/// Lowercase alphanumerics only, so "Se7en" and "se7en", or "Amélie" and
+/// "Amélie." compare equal.
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Finds the title called `name` that came out in `year`, preferring movies.
+pub(crate) fn find_by_name(
+    provider: &dyn MetadataProvider,
+    name: &str,
+    year: Option<u16>,
+) -> Result<(i32, TitleType), FetchError> {
+    let label = match year {
+        Some(year) => format!("{name} ({year})"),
+        None => name.to_owned(),
+    };
+    let wanted = normalize_name(name);
+    let hits = search_titles(provider, name)?;
+    let same_name: Vec<&SearchHit> = hits
+        .iter()
+        .filter(|hit| normalize_name(&hit.title) == wanted)
+        .collect();
+    // Release years differ by one now and then, e.g. festival vs. theatrical.
+    let tolerances: &[u16] = if year.is_some() { &[0, 1] } else { &[0] };
+    for tolerance in tolerances {
+        let matches: Vec<&&SearchHit> = same_name
+            .iter()
+            .filter(|hit| match (year, hit.year) {
+                (Some(year), Some(hit_year)) => year.abs_diff(hit_year) <= *tolerance,
+                (Some(_), None) => false,
+                (None, _) => true,
+            })
+            .collect();
+        let movies: Vec<&&&SearchHit> = matches
+            .iter()
+            .filter(|hit| hit.ty == TitleType::Movie)
+            .collect();
+        match (&matches[..], &movies[..]) {
+            (_, [movie]) => return Ok((movie.id, movie.ty)),
+            ([hit], []) => return Ok((hit.id, hit.ty)),
+            ([], _) => continue,
+            _ => return Err(FetchError::AmbiguousMatch(label)),
+        }
+    }
+    Err(FetchError::NoMatch(label))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{FixtureProvider, TmdbProvider};
+    use crate::settings::Settings;
+    use crate::stub::{StubServer, TEST_FIXTURES};
+
+    fn stub_provider(server: &StubServer) -> TmdbProvider {
+        let settings = Settings {
+            credential: "0123456789abcdef".to_owned(),
+            ..Default::default()
+        };
+        TmdbProvider::new(settings).with_base_url(server.url())
+    }
+
+    fn check_title_id_from_imdb(provider: &dyn MetadataProvider) {
+        let leon = get_title_id_from_imdb(provider, TitleId(110413)).unwrap();
+        assert_eq!(leon, (101, TitleType::Movie));
+        let breaking_bad = get_title_id_from_imdb(provider, TitleId(903747)).unwrap();
+        assert_eq!(breaking_bad, (1396, TitleType::Series));
+        let missing = get_title_id_from_imdb(provider, TitleId(1));
+        assert!(matches!(
+            missing,
+            Err(FetchError::Status { status: 404, .. })
+        ));
+    }
+
+    fn check_title_from_tmdb(provider: &dyn MetadataProvider) {
+        let leon = get_title_from_tmdb(provider, 101, TitleType::Movie, Some(9.0)).unwrap();
+        assert_eq!(leon.id, TitleId(110413));
+        assert_eq!(leon.title, "Léon: The Professional");
+        assert_eq!(leon.year, 1994);
+        assert_eq!(leon.my_rating, Some(9.0));
+        assert_eq!(leon.directors, ["Luc Besson"]);
+        assert_eq!(leon.actors, ["Jean Reno", "Natalie Portman", "Gary Oldman"]);
+        assert_eq!(leon.genres, ["Crime", "Drama", "Action"]);
+        assert_eq!(leon.tmdb_id, Some(101));
+        assert_eq!(leon.fetched_from, provider.name());
+
+        let breaking_bad = get_title_from_tmdb(provider, 1396, TitleType::Series, None).unwrap();
+        assert_eq!(breaking_bad.id, TitleId(903747));
+        assert_eq!(breaking_bad.year, 2008);
+        assert_eq!(breaking_bad.directors, ["Vince Gilligan"]);
+    }
+
+    fn check_search_titles(provider: &dyn MetadataProvider) {
+        let hits = search_titles(provider, "Mr. Nobody").unwrap();
+        let hits: Vec<_> = hits.iter().map(|h| (h.id, h.ty, h.year)).collect();
+        assert_eq!(
+            hits,
+            [
+                (31011, TitleType::Movie, Some(2009)),
+                (225634, TitleType::Series, None),
+            ]
+        );
+        assert!(search_titles(provider, "Mr. Smith").is_err());
+    }
+
+    #[test]
+    fn fetches_from_fixtures() {
+        let provider = FixtureProvider::new(TEST_FIXTURES);
+        check_title_id_from_imdb(&provider);
+        check_title_from_tmdb(&provider);
+        check_search_titles(&provider);
+    }
+
+    #[test]
+    fn fetches_from_stub_server() {
+        let server = StubServer::start(TEST_FIXTURES).unwrap();
+        let provider = stub_provider(&server);
+        check_title_id_from_imdb(&provider);
+        check_title_from_tmdb(&provider);
+        check_search_titles(&provider);
+    }
+}
@@ -0,0 +1,485 @@
+use std::{cmp::Ordering, collections::BTreeSet, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::models::TitleType;
+use crate::my_titles::Title;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SortKey {
+    #[default]
+    Title,
+    Year,
+    /// TMDB's rating.
+    Rating,
+    MyRating,
+    Added,
+}
+
+impl SortKey {
+    const ALL: [Self; 5] = [
+        SortKey::Title,
+        SortKey::Year,
+        SortKey::Rating,
+        SortKey::MyRating,
+        SortKey::Added,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            SortKey::Title => "Title",
+            SortKey::Year => "Year",
+            SortKey::Rating => "TMDB rating",
+            SortKey::MyRating => "My rating",
+            SortKey::Added => "Date added",
+        }
+    }
+
+    fn compare(self, a: &Title, b: &Title) -> Ordering {
+        let by_title = || a.title.cmp(&b.title).then(a.year.cmp(&b.year));
+        match self {
+            SortKey::Title => by_title(),
+            SortKey::Year => a.year.cmp(&b.year).then_with(by_title),
+            SortKey::Rating => a.rating.total_cmp(&b.rating).then_with(by_title),
+            // Unrated titles go first, like the oldest ones do for `Added`.
+            SortKey::MyRating => a
+                .my_rating
+                .partial_cmp(&b.my_rating)
+                .unwrap_or(Ordering::Equal)
+                .then_with(by_title),
+            SortKey::Added => a.added_at.cmp(&b.added_at).then_with(by_title),
+        }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "title" => Ok(SortKey::Title),
+            "year" => Ok(SortKey::Year),
+            "rating" => Ok(SortKey::Rating),
+            "my-rating" => Ok(SortKey::MyRating),
+            "added" => Ok(SortKey::Added),
+            _ => Err(FilterError::BadValue("sort", s.to_owned())),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum FilterError {
+    #[error(
+        "unknown filter \"{0}\", expected search, type, genre, year, rating, rated, unrated, my-rating or person"
+    )]
+    Unknown(String),
+    #[error("bad value for {0}: \"{1}\"")]
+    BadValue(&'static str, String),
+}
+
+/// Reads `from..to`, `from..`, `..to` or a single value, open ends taking
+/// `min` and `max`.
+fn parse_range<T: FromStr + Copy>(
+    name: &'static str,
+    value: &str,
+    min: T,
+    max: T,
+) -> Result<(T, T), FilterError> {
+    let bad = || FilterError::BadValue(name, value.to_owned());
+    let bound = |s: &str, default: T| match s.trim() {
+        "" => Ok(default),
+        s => s.parse().map_err(|_| bad()),
+    };
+    match value.split_once("..") {
+        Some((from, to)) => Ok((bound(from, min)?, bound(to, max)?)),
+        None if value.trim().is_empty() => Err(bad()),
+        None => {
+            let value = bound(value, min)?;
+            Ok((value, value))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RatedFilter {
+    #[default]
+    Any,
+    Rated,
+    Unrated,
+}
+
+/// How the My Titles tab sorts and narrows down the library. Stored between
+/// sessions.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TitleFilter {
+    pub sort: SortKey,
+    pub descending: bool,
+    /// Matched against the title and the description, ignoring case.
+    pub search: String,
+    pub ty: Option<TitleType>,
+    pub genre: Option<String>,
+    pub years: Option<(u16, u16)>,
+    /// Range of TMDB's `rating`.
+    pub ratings: Option<(f32, f32)>,
+    pub rated: RatedFilter,
+    /// Range of `my_rating`, leaves unrated titles out.
+    pub my_ratings: Option<(f32, f32)>,
+    /// Part of the name of a director or actor.
+    pub person: String,
+}
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(needle)
+}
+
+impl TitleFilter {
+    pub fn matches(&self, title: &Title) -> bool {
+        let search = self.search.trim().to_lowercase();
+        if !search.is_empty()
+            && !contains_ignore_case(&title.title, &search)
+            && !contains_ignore_case(&title.description, &search)
+        {
+            return false;
+        }
+        if self.ty.is_some_and(|ty| ty != title.ty) {
+            return false;
+        }
+        if let Some(genre) = &self.genre {
+            if !title.genres.contains(genre) {
+                return false;
+            }
+        }
+        if let Some((from, to)) = self.years {
+            if !(from..=to).contains(&title.year) {
+                return false;
+            }
+        }
+        if let Some((from, to)) = self.ratings {
+            if !(from..=to).contains(&title.rating) {
+                return false;
+            }
+        }
+        match (self.rated, title.my_rating) {
+            (RatedFilter::Rated, None) | (RatedFilter::Unrated, Some(_)) => return false,
+            _ => {}
+        }
+        if let Some((from, to)) = self.my_ratings {
+            if !title.my_rating.is_some_and(|r| (from..=to).contains(&r)) {
+                return false;
+            }
+        }
+        let person = self.person.trim().to_lowercase();
+        if !person.is_empty()
+            && !title
+                .directors
+                .iter()
+                .chain(&title.actors)
+                .any(|name| contains_ignore_case(name, &person))
+        {
+            return false;
+        }
+        true
+    }
+
+    pub fn sort(&self, titles: &mut [&Title]) {
+        titles.sort_by(|a, b| {
+            let ordering = self.sort.compare(a, b);
+            if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
+
+    /// Sets one condition written as `name=value`, the way the command line
+    /// takes them, e.g. `genre=Drama`, `year=1990..1999` or `rated`.
+    pub fn add_condition(&mut self, condition: &str) -> Result<(), FilterError> {
+        let (name, value) = condition.split_once('=').unwrap_or((condition, ""));
+        let value = value.trim();
+        match name.trim() {
+            "search" => self.search = value.to_owned(),
+            "type" => {
+                self.ty = Some(match value.to_lowercase().as_str() {
+                    "movie" => TitleType::Movie,
+                    "series" => TitleType::Series,
+                    _ => return Err(FilterError::BadValue("type", value.to_owned())),
+                })
+            }
+            "genre" => self.genre = Some(value.to_owned()),
+            "year" => self.years = Some(parse_range("year", value, 0, u16::MAX)?),
+            "rating" => self.ratings = Some(parse_range("rating", value, 0.0, 10.0)?),
+            "rated" => self.rated = RatedFilter::Rated,
+            "unrated" => self.rated = RatedFilter::Unrated,
+            "my-rating" => self.my_ratings = Some(parse_range("my-rating", value, 1.0, 10.0)?),
+            "person" => self.person = value.to_owned(),
+            _ => return Err(FilterError::Unknown(condition.to_owned())),
+        }
+        Ok(())
+    }
+
+    /// Whether anything but the sort order is set.
+    pub fn is_filtering(&self) -> bool {
+        let sorted_only = TitleFilter {
+            sort: self.sort,
+            descending: self.descending,
+            ..Default::default()
+        };
+        *self != sorted_only
+    }
+
+    /// Search field and sort order, with the other filters in a collapsible
+    /// section below. `genres` and `years` are the ones in the library.
+    pub fn show_toolbar(
+        &mut self,
+        ui: &mut egui::Ui,
+        genres: &BTreeSet<String>,
+        years: (u16, u16),
+    ) {
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.search)
+                    .hint_text("🔍 Search titles and descriptions")
+                    .desired_width(250.0),
+            );
+            ui.separator();
+            ui.label("Sort by");
+            egui::ComboBox::from_id_source("sort_key")
+                .selected_text(self.sort.name())
+                .show_ui(ui, |ui| {
+                    for key in SortKey::ALL {
+                        ui.selectable_value(&mut self.sort, key, key.name());
+                    }
+                });
+            let arrow = if self.descending { "⬇" } else { "⬆" };
+            ui.toggle_value(&mut self.descending, arrow)
+                .on_hover_text("Descending");
+            if self.is_filtering() && ui.button("Clear filters").clicked() {
+                *self = TitleFilter {
+                    sort: self.sort,
+                    descending: self.descending,
+                    ..Default::default()
+                };
+            }
+        });
+        egui::CollapsingHeader::new("Filters")
+            .id_source("title_filters")
+            .show(ui, |ui| {
+                egui::Grid::new("title_filter_grid")
+                    .num_columns(2)
+                    .spacing([12.0, 6.0])
+                    .show(ui, |ui| {
+                        ui.label("Type");
+                        ui.horizontal(|ui| {
+                            ui.selectable_value(&mut self.ty, None, "Any");
+                            ui.selectable_value(&mut self.ty, Some(TitleType::Movie), "Movies");
+                            ui.selectable_value(&mut self.ty, Some(TitleType::Series), "Series");
+                        });
+                        ui.end_row();
+
+                        ui.label("Genre");
+                        egui::ComboBox::from_id_source("genre_filter")
+                            .selected_text(self.genre.as_deref().unwrap_or("Any"))
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut self.genre, None, "Any");
+                                for genre in genres {
+                                    ui.selectable_value(
+                                        &mut self.genre,
+                                        Some(genre.clone()),
+                                        genre,
+                                    );
+                                }
+                            });
+                        ui.end_row();
+
+                        ui.label("Years");
+                        ui.horizontal(|ui| {
+                            let mut enabled = self.years.is_some();
+                            if ui.checkbox(&mut enabled, "").changed() {
+                                self.years = enabled.then_some(years);
+                            }
+                            if let Some((from, to)) = &mut self.years {
+                                ui.add(egui::DragValue::new(from).clamp_range(years.0..=*to));
+                                ui.label("to");
+                                ui.add(egui::DragValue::new(to).clamp_range(*from..=years.1));
+                            }
+                        });
+                        ui.end_row();
+
+                        ui.label("TMDB rating");
+                        ui.horizontal(|ui| {
+                            let mut enabled = self.ratings.is_some();
+                            if ui.checkbox(&mut enabled, "between").changed() {
+                                self.ratings = enabled.then_some((0.0, 10.0));
+                            }
+                            if let Some((from, to)) = &mut self.ratings {
+                                ui.add(
+                                    egui::DragValue::new(from).speed(0.1).clamp_range(0.0..=*to),
+                                );
+                                ui.label("and");
+                                ui.add(
+                                    egui::DragValue::new(to)
+                                        .speed(0.1)
+                                        .clamp_range(*from..=10.0),
+                                );
+                            }
+                        });
+                        ui.end_row();
+
+                        ui.label("My rating");
+                        ui.horizontal(|ui| {
+                            ui.selectable_value(&mut self.rated, RatedFilter::Any, "Any");
+                            ui.selectable_value(&mut self.rated, RatedFilter::Rated, "Rated");
+                            ui.selectable_value(&mut self.rated, RatedFilter::Unrated, "Unrated");
+                            if self.rated == RatedFilter::Unrated {
+                                self.my_ratings = None;
+                                return;
+                            }
+                            ui.separator();
+                            let mut enabled = self.my_ratings.is_some();
+                            if ui.checkbox(&mut enabled, "between").changed() {
+                                self.my_ratings = enabled.then_some((1.0, 10.0));
+                            }
+                            if let Some((from, to)) = &mut self.my_ratings {
+                                ui.add(
+                                    egui::DragValue::new(from).speed(0.5).clamp_range(1.0..=*to),
+                                );
+                                ui.label("and");
+                                ui.add(
+                                    egui::DragValue::new(to)
+                                        .speed(0.5)
+                                        .clamp_range(*from..=10.0),
+                                );
+                            }
+                        });
+                        ui.end_row();
+
+                        ui.label("Director or actor");
+                        ui.text_edit_singleline(&mut self.person);
+                        ui.end_row();
+                    });
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn titles() -> Vec<Title> {
+        let mut heat = Title::test(113277, "Heat");
+        heat.year = 1995;
+        heat.rating = 7.9;
+        heat.my_rating = Some(9.0);
+        heat.genres = vec!["Crime".to_owned(), "Thriller".to_owned()];
+        heat.directors = vec!["Michael Mann".to_owned()];
+        heat.actors = vec!["Al Pacino".to_owned(), "Robert De Niro".to_owned()];
+        let mut up = Title::test(1049413, "Up");
+        up.year = 2009;
+        up.rating = 7.9;
+        up.genres = vec!["Animation".to_owned()];
+        up.description = "A balloon salesman flies his house to South America.".to_owned();
+        let mut bad = Title::test(903747, "Breaking Bad");
+        bad.ty = TitleType::Series;
+        bad.year = 2008;
+        bad.rating = 8.9;
+        bad.my_rating = Some(7.5);
+        bad.genres = vec!["Crime".to_owned(), "Drama".to_owned()];
+        vec![heat, up, bad]
+    }
+
+    fn filter(conditions: &[&str]) -> TitleFilter {
+        let mut filter = TitleFilter::default();
+        for condition in conditions {
+            filter.add_condition(condition).unwrap();
+        }
+        filter
+    }
+
+    fn matching(filter: &TitleFilter) -> Vec<String> {
+        let titles = titles();
+        let mut matched: Vec<&Title> = titles.iter().filter(|t| filter.matches(t)).collect();
+        filter.sort(&mut matched);
+        matched.into_iter().map(|t| t.title.clone()).collect()
+    }
+
+    #[test]
+    fn matches_every_condition() {
+        assert_eq!(matching(&filter(&[])), ["Breaking Bad", "Heat", "Up"]);
+        assert_eq!(matching(&filter(&["search=BALLOON"])), ["Up"]);
+        assert_eq!(matching(&filter(&["type=Series"])), ["Breaking Bad"]);
+        assert_eq!(
+            matching(&filter(&["genre=Crime"])),
+            ["Breaking Bad", "Heat"]
+        );
+        assert_eq!(matching(&filter(&["year=2000.."])), ["Breaking Bad", "Up"]);
+        assert_eq!(matching(&filter(&["year=1995"])), ["Heat"]);
+        assert_eq!(matching(&filter(&["rating=8.5.."])), ["Breaking Bad"]);
+        assert_eq!(matching(&filter(&["rating=..8"])), ["Heat", "Up"]);
+        assert_eq!(matching(&filter(&["rated"])), ["Breaking Bad", "Heat"]);
+        assert_eq!(matching(&filter(&["unrated"])), ["Up"]);
+        assert_eq!(matching(&filter(&["my-rating=8..10"])), ["Heat"]);
+        assert_eq!(matching(&filter(&["person=de niro"])), ["Heat"]);
+        assert_eq!(matching(&filter(&["genre=Crime", "type=movie"])), ["Heat"]);
+    }
+
+    #[test]
+    fn sorts_by_the_key_then_the_title() {
+        let sorted = |sort, descending| {
+            matching(&TitleFilter {
+                sort,
+                descending,
+                ..Default::default()
+            })
+        };
+        assert_eq!(sorted(SortKey::Year, false), ["Heat", "Breaking Bad", "Up"]);
+        assert_eq!(
+            sorted(SortKey::Rating, false),
+            ["Heat", "Up", "Breaking Bad"]
+        );
+        assert_eq!(
+            sorted(SortKey::Rating, true),
+            ["Breaking Bad", "Up", "Heat"]
+        );
+        assert_eq!(
+            sorted(SortKey::MyRating, false),
+            ["Up", "Breaking Bad", "Heat"]
+        );
+    }
+
+    #[test]
+    fn reads_conditions() {
+        let filter = filter(&["year=1990..1999", "rating=7", "my-rating=..8", "type=movie"]);
+        assert_eq!(filter.years, Some((1990, 1999)));
+        assert_eq!(filter.ratings, Some((7.0, 7.0)));
+        assert_eq!(filter.my_ratings, Some((1.0, 8.0)));
+        assert_eq!(filter.ty, Some(TitleType::Movie));
+        assert!(filter.is_filtering());
+        assert!(!TitleFilter::default().is_filtering());
+        assert_eq!("my-rating".parse::<SortKey>().unwrap(), SortKey::MyRating);
+    }
+
+    #[test]
+    fn rejects_bad_conditions() {
+        let error = |condition| {
+            TitleFilter::default()
+                .add_condition(condition)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(error("year=199x"), "bad value for year: \"199x\"");
+        assert_eq!(error("year="), "bad value for year: \"\"");
+        assert_eq!(
+            error("rating=7..great"),
+            "bad value for rating: \"7..great\""
+        );
+        assert_eq!(
+            error("type=documentary"),
+            "bad value for type: \"documentary\""
+        );
+        assert!(error("colour=red").starts_with("unknown filter \"colour=red\""));
+        assert!("best".parse::<SortKey>().is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    sync::{mpsc::channel, Arc, Condvar, Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::provider::FetchError;
+
+/// How hard [`HttpClient`] may hit a server.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// Requests per second in the long run.
+    pub rate: f64,
+    /// Requests that may go out at once after a quiet spell.
+    pub burst: u32,
+    /// Requests that may be waiting on the server at the same time.
+    pub max_concurrent: usize,
+    /// Retries of a request answered with 429 or a 5xx, or not answered at
+    /// all.
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for each one after.
+    pub backoff: Duration,
+    /// Longest wait between two tries, `Retry-After` included.
+    pub max_backoff: Duration,
+    /// How long to wait for a response before giving up.
+    pub timeout: Duration,
+}
+
+impl Default for HttpConfig {
+    /// Stays well under TMDB's limit of around 50 requests per second.
+    fn default() -> Self {
+        Self {
+            rate: 20.0,
+            burst: 20,
+            max_concurrent: 8,
+            max_retries: 4,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            timeout: Duration::from_secs(20),
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Requests waiting on the server, shared with their callbacks so a request
+/// holds its slot until it is answered, even after the caller gave up on it.
+#[derive(Default)]
+struct Slots {
+    in_flight: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl Slots {
+    fn release(&self) {
+        *self.in_flight.lock().unwrap() -= 1;
+        self.freed.notify_one();
+    }
+}
+
+/// Sends requests within a rate limit and a number of concurrent requests,
+/// retrying the ones the server turned away for now.
+pub struct HttpClient {
+    config: HttpConfig,
+    bucket: Mutex<TokenBucket>,
+    slots: Arc<Slots>,
+}
+
+/// Answers that may go away by asking again later.
+fn is_transient(status: u16) -> bool {
+    status == 429 || (500..=599).contains(&status)
+}
+
+/// The wait asked for by a `Retry-After` header, in seconds or as a date.
+fn retry_after(response: &ehttp::Response) -> Option<Duration> {
+    let value = response.headers.get("retry-after")?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value)
+        .ok()?
+        .with_timezone(&Utc);
+    (at - Utc::now()).to_std().ok()
+}
+
+impl HttpClient {
+    pub fn new(config: HttpConfig) -> Self {
+        Self {
+            bucket: Mutex::new(TokenBucket {
+                tokens: config.burst as f64,
+                updated: Instant::now(),
+            }),
+            config,
+            slots: Arc::default(),
+        }
+    }
+
+    /// The client every request to TMDB goes through, so the limits hold
+    /// across providers.
+    pub fn shared() -> Arc<Self> {
+        static SHARED: OnceLock<Arc<HttpClient>> = OnceLock::new();
+        Arc::clone(SHARED.get_or_init(|| Arc::new(Self::new(HttpConfig::default()))))
+    }
+
+    /// Blocks until the rate limit allows another request.
+    fn take_token(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                let refill = now.duration_since(bucket.updated).as_secs_f64() * self.config.rate;
+                bucket.tokens = (bucket.tokens + refill).min(self.config.burst as f64);
+                bucket.updated = now;
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.config.rate)
+            };
+            thread::sleep(wait);
+        }
+    }
+
+    /// Sends `request` once, giving up after the timeout.
+    fn send(&self, request: ehttp::Request) -> Result<ehttp::Response, FetchError> {
+        {
+            let mut in_flight = self.slots.in_flight.lock().unwrap();
+            while *in_flight >= self.config.max_concurrent {
+                in_flight = self.slots.freed.wait(in_flight).unwrap();
+            }
+            *in_flight += 1;
+        }
+        let (tx, rx) = channel();
+        let slots = Arc::clone(&self.slots);
+        ehttp::fetch(request, move |response| {
+            slots.release();
+            // The receiving end only goes away if the caller gave up, nobody to tell then.
+            let _ = tx.send(response);
+        });
+        match rx.recv_timeout(self.config.timeout) {
+            Ok(response) => response.map_err(FetchError::Network),
+            Err(_) => Err(FetchError::Network(format!(
+                "no response in {}s",
+                self.config.timeout.as_secs()
+            ))),
+        }
+    }
+
+    /// Sends `request`, retrying with exponential backoff while the server
+    /// answers 429 or a 5xx, or the request fails on the way, e.g. on a reset
+    /// connection or a timeout. Returns the body of the first other answer.
+    pub fn fetch(&self, request: &ehttp::Request) -> Result<Vec<u8>, FetchError> {
+        let mut backoff = self.config.backoff;
+        let mut retries = 0;
+        loop {
+            self.take_token();
+            let last_try = retries == self.config.max_retries;
+            let wait = match self.send(request.clone()) {
+                Ok(response) if response.ok => return Ok(response.bytes),
+                Ok(response) if !is_transient(response.status) || last_try => {
+                    return Err(FetchError::Status {
+                        status: response.status,
+                        status_text: response.status_text,
+                    })
+                }
+                Ok(response) => retry_after(&response).unwrap_or(backoff),
+                Err(err) if last_try => return Err(err),
+                Err(_) => backoff,
+            };
+            thread::sleep(wait.min(self.config.max_backoff));
+            backoff = (backoff * 2).min(self.config.max_backoff);
+            retries += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub::{Fault, StubServer, TEST_FIXTURES};
+
+    fn client() -> HttpClient {
+        HttpClient::new(HttpConfig {
+            max_retries: 2,
+            backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(2),
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        })
+    }
+
+    fn movie(server: &StubServer) -> ehttp::Request {
+        ehttp::Request::get(format!("{}/movie/101", server.url()))
+    }
+
+    #[test]
+    fn waits_as_long_as_retry_after_says() {
+        let server = StubServer::start(TEST_FIXTURES).unwrap();
+        server.inject([Fault::TooManyRequests {
+            retry_after: Some(1),
+        }]);
+        let started = Instant::now();
+        let body = client().fetch(&movie(&server)).unwrap();
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert!(body.starts_with(b"{"));
+        assert_eq!(server.requests(), 2);
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let server = StubServer::start(TEST_FIXTURES).unwrap();
+        server.inject([Fault::ServerError(503)]);
+        assert!(client().fetch(&movie(&server)).is_ok());
+        assert_eq!(server.requests(), 2);
+    }
+
+    #[test]
+    fn retries_dropped_connections() {
+        let server = StubServer::start(TEST_FIXTURES).unwrap();
+        server.inject([Fault::Disconnect, Fault::Disconnect]);
+        assert!(client().fetch(&movie(&server)).is_ok());
+        assert_eq!(server.requests(), 3);
+
+        server.inject([Fault::Disconnect; 3]);
+        let result = client().fetch(&movie(&server));
+        assert!(matches!(result, Err(FetchError::Network(_))));
+        assert_eq!(server.requests(), 6);
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let server = StubServer::start(TEST_FIXTURES).unwrap();
+        server.inject([Fault::ServerError(503); 3]);
+        let result = client().fetch(&movie(&server));
+        assert!(matches!(
+            result,
+            Err(FetchError::Status { status: 503, .. })
+        ));
+        assert_eq!(server.requests(), 3);
+    }
+}
@@ -0,0 +1,732 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+};
+
+use thiserror::Error;
+
+use crate::export::{LibraryExport, EXPORT_VERSION};
+use crate::jobs::Source;
+use crate::models::{trakt, CsvTitleType, ImdbCsvRow, LetterboxdCsvRow, TitleType, WatchStatus};
+use crate::my_titles::{Title, TitleList, UserFields};
+
+/// Where a record was read from, to report on it.
+#[derive(Debug, Clone)]
+pub struct Origin {
+    pub file: String,
+    /// Line of a CSV row or position of a JSON entry, 0 for the whole file.
+    pub line: u64,
+    /// What the record calls the title.
+    pub label: String,
+    /// What the library can't keep of the record, e.g. an IMDb title type it
+    /// files under a broader one.
+    pub note: Option<String>,
+}
+
+/// A record of an import file, reduced to what's needed to fetch the title.
+#[derive(Debug, Clone)]
+pub struct ImportItem {
+    pub source: Source,
+    pub user: UserFields,
+    pub format: ImportFormat,
+    pub origin: Origin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// IMDb's ratings export.
+    Imdb,
+    /// IMDb's watchlist export, the ratings columns and a position.
+    ImdbWatchlist,
+    /// Letterboxd's `ratings.csv`, `watched.csv` or `diary.csv`.
+    Letterboxd,
+    /// Trakt's `watched-*.json` or `ratings-*.json`.
+    Trakt,
+}
+
+impl ImportFormat {
+    /// Tells the format from the CSV header.
+    fn detect(headers: &csv::StringRecord) -> Option<Self> {
+        let has = |name| headers.iter().any(|h| h == name);
+        if has("Const") && has("Position") {
+            Some(Self::ImdbWatchlist)
+        } else if has("Const") {
+            Some(Self::Imdb)
+        } else if has("Letterboxd URI") {
+            Some(Self::Letterboxd)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Imdb => "IMDb ratings",
+            Self::ImdbWatchlist => "IMDb watchlist",
+            Self::Letterboxd => "Letterboxd",
+            Self::Trakt => "Trakt",
+        }
+    }
+
+    /// Whether records of titles already in the library update them. Trakt
+    /// and Letterboxd exports are split across files, so a title is often
+    /// seen again, e.g. watched first and rated later.
+    pub fn merges(self) -> bool {
+        matches!(self, Self::Trakt | Self::Letterboxd)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("couldn't open the file: {0}")]
+    Io(#[from] io::Error),
+    #[error("wrong file format: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("wrong file format: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error(
+        "unknown file format! Make sure you exported it from IMDb, Letterboxd, Trakt or meepls"
+    )]
+    UnknownFormat,
+    #[error("exported by a newer version of meepls (format {0})")]
+    UnsupportedVersion(u32),
+}
+
+/// What became of a record.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Still being fetched.
+    Pending,
+    Imported,
+    /// Already in the library, its ratings or watches were updated.
+    Updated,
+    /// Already in the library, left as is.
+    Duplicate,
+    /// Another record of a title earlier in the import, folded into the one
+    /// at `file` and `line`.
+    Merged {
+        file: String,
+        line: u64,
+    },
+    /// An IMDb title type the app has no equivalent for, e.g. `tvEpisode`.
+    UnknownType(String),
+    /// TMDB couldn't be reached, it's added once it can be.
+    Queued,
+    Failed(String),
+}
+
+impl Outcome {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Pending => "Pending",
+            Outcome::Imported => "Imported",
+            Outcome::Updated => "Updated",
+            Outcome::Duplicate | Outcome::Merged { .. } => "Skipped duplicate",
+            Outcome::UnknownType(_) => "Unknown type",
+            Outcome::Queued => "Queued",
+            Outcome::Failed(_) => "Failed",
+        }
+    }
+
+    pub fn reason(&self) -> String {
+        match self {
+            Outcome::UnknownType(ty) => format!("\"{ty}\" titles can't be imported"),
+            Outcome::Failed(reason) => reason.clone(),
+            Outcome::Duplicate => "already in your library".to_owned(),
+            Outcome::Merged { file, line } => format!("merged into line {line} of {file}"),
+            Outcome::Queued => "added once TMDB can be reached".to_owned(),
+            _ => String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReportRow {
+    pub origin: Origin,
+    pub outcome: Outcome,
+}
+
+impl ReportRow {
+    /// The outcome's reason, followed by the record's note.
+    pub fn reason(&self) -> String {
+        let reason = self.outcome.reason();
+        match &self.origin.note {
+            Some(note) if reason.is_empty() => note.clone(),
+            Some(note) => format!("{reason}, {note}"),
+            None => reason,
+        }
+    }
+}
+
+/// What an import did with every record it read.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub rows: Vec<ReportRow>,
+}
+
+impl ImportReport {
+    /// Adds a row, returning its index.
+    pub fn push(&mut self, origin: Origin, outcome: Outcome) -> usize {
+        self.rows.push(ReportRow { origin, outcome });
+        self.rows.len() - 1
+    }
+
+    pub fn count(&self, pred: impl Fn(&Outcome) -> bool) -> usize {
+        self.rows.iter().filter(|row| pred(&row.outcome)).count()
+    }
+
+    pub fn write_csv(&self, path: &Path) -> csv::Result<()> {
+        let mut wtr = csv::Writer::from_path(path)?;
+        wtr.write_record(["File", "Line", "Title", "Result", "Reason"])?;
+        for row in &self.rows {
+            let line = match row.origin.line {
+                0 => String::new(),
+                line => line.to_string(),
+            };
+            wtr.write_record([
+                row.origin.file.as_str(),
+                &line,
+                &row.origin.label,
+                row.outcome.name(),
+                &row.reason(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+/// The records of a file, and the ones that couldn't be read.
+#[derive(Debug, Default)]
+pub struct ReadFile {
+    pub items: Vec<ImportItem>,
+    /// Titles that need no fetching, from a library export.
+    pub titles: Vec<(Origin, Title)>,
+    pub lists: Vec<TitleList>,
+    pub rejected: Vec<ReportRow>,
+}
+
+/// Letterboxd rates from 0.5 to 5 stars, the app from 1 to 10.
+fn letterboxd_rating(stars: f32) -> f32 {
+    (stars * 2.0).clamp(1.0, 10.0)
+}
+
+/// Reads the titles of an IMDb or Letterboxd CSV export, or of a Trakt or
+/// library JSON export. Only fails if the file can't be read at all, bad
+/// records are rejected one by one.
+pub fn read_file(path: &Path) -> Result<ReadFile, ImportError> {
+    let file = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => read_json(path, file),
+        Some("csv") => read_csv(path, file),
+        _ => Err(ImportError::UnknownFormat),
+    }
+}
+
+/// Reads the titles of an IMDb or Letterboxd CSV export.
+fn read_csv(path: &Path, file: String) -> Result<ReadFile, ImportError> {
+    let mut rdr = csv::Reader::from_reader(File::open(path)?);
+    let headers = rdr.headers()?.clone();
+    let format = ImportFormat::detect(&headers).ok_or(ImportError::UnknownFormat)?;
+    let title_column = headers.iter().position(|h| h == "Title" || h == "Name");
+    let mut read = ReadFile::default();
+    for record in rdr.records() {
+        let (origin, parsed) = match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, |p| p.line());
+                let label = title_column
+                    .and_then(|i| record.get(i))
+                    .filter(|label| !label.is_empty())
+                    .map_or_else(|| format!("row {line}"), str::to_owned);
+                let mut origin = Origin {
+                    file: file.clone(),
+                    line,
+                    label,
+                    note: None,
+                };
+                let parsed =
+                    parse_csv_row(format, &record, &headers).map(|(source, user, note)| {
+                        origin.note = note;
+                        (source, user)
+                    });
+                (origin, parsed)
+            }
+            Err(err) => {
+                let line = err.position().map_or(0, |p| p.line());
+                let origin = Origin {
+                    file: file.clone(),
+                    line,
+                    label: format!("row {line}"),
+                    note: None,
+                };
+                (origin, Err(Outcome::Failed(err.to_string())))
+            }
+        };
+        match parsed {
+            Ok((source, user)) => read.items.push(ImportItem {
+                source,
+                user,
+                format,
+                origin,
+            }),
+            Err(outcome) => read.rejected.push(ReportRow { origin, outcome }),
+        }
+    }
+    Ok(read)
+}
+
+/// Parses a row into what to fetch, the user's fields and a note for the
+/// report.
+fn parse_csv_row(
+    format: ImportFormat,
+    record: &csv::StringRecord,
+    headers: &csv::StringRecord,
+) -> Result<(Source, UserFields, Option<String>), Outcome> {
+    let failed = |err: csv::Error| Outcome::Failed(err.to_string());
+    match format {
+        ImportFormat::Imdb | ImportFormat::ImdbWatchlist => {
+            let row: ImdbCsvRow = record.deserialize(Some(headers)).map_err(failed)?;
+            // Older exports don't have the column, their titles are tried anyway.
+            let mut note = None;
+            if let Some(ty) = row.ty.filter(|ty| !ty.is_empty()) {
+                let parsed = ty
+                    .parse::<CsvTitleType>()
+                    .map_err(|_| Outcome::UnknownType(ty.clone()))?;
+                if parsed.is_narrower() {
+                    note = Some(format!("\"{ty}\" is kept as a {}", TitleType::from(parsed)));
+                }
+            }
+            let status = match format {
+                ImportFormat::ImdbWatchlist => WatchStatus::Watchlist,
+                _ => WatchStatus::Watched,
+            };
+            let user = UserFields {
+                my_rating: row.rating,
+                status,
+                ..Default::default()
+            };
+            Ok((Source::Imdb(row.id), user, note))
+        }
+        ImportFormat::Letterboxd => {
+            let row: LetterboxdCsvRow = record.deserialize(Some(headers)).map_err(failed)?;
+            let source = Source::Name {
+                name: row.name,
+                year: row.year,
+            };
+            let user = UserFields {
+                my_rating: row.rating.map(letterboxd_rating),
+                ..Default::default()
+            };
+            Ok((source, user, None))
+        }
+        ImportFormat::Trakt => unreachable!("Trakt exports aren't CSV"),
+    }
+}
+
+/// Tells a library export, an object, from a Trakt one, an array.
+fn read_json(path: &Path, file: String) -> Result<ReadFile, ImportError> {
+    let value: serde_json::Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    match value {
+        serde_json::Value::Array(entries) => Ok(read_trakt(entries, file)),
+        serde_json::Value::Object(_) => read_library(value, file),
+        _ => Err(ImportError::UnknownFormat),
+    }
+}
+
+fn read_library(value: serde_json::Value, file: String) -> Result<ReadFile, ImportError> {
+    let version = value.get("version").and_then(|v| v.as_u64());
+    match version {
+        Some(version) if version > EXPORT_VERSION as u64 => {
+            return Err(ImportError::UnsupportedVersion(version as u32))
+        }
+        Some(_) => {}
+        None => return Err(ImportError::UnknownFormat),
+    }
+    let export: LibraryExport = serde_json::from_value(value)?;
+    let titles = export
+        .titles
+        .into_iter()
+        .enumerate()
+        .map(|(i, title)| {
+            let origin = Origin {
+                file: file.clone(),
+                line: i as u64 + 1,
+                label: title.title.clone(),
+                note: None,
+            };
+            (origin, title)
+        })
+        .collect();
+    Ok(ReadFile {
+        titles,
+        lists: export.lists,
+        ..Default::default()
+    })
+}
+
+/// Reads the movies and shows of a Trakt JSON export. Episode and season
+/// ratings are rejected as unknown types.
+fn read_trakt(entries: Vec<serde_json::Value>, file: String) -> ReadFile {
+    let mut read = ReadFile::default();
+    for (i, value) in entries.into_iter().enumerate() {
+        let line = i as u64 + 1;
+        let label = ["/movie/title", "/show/title"]
+            .into_iter()
+            .find_map(|pointer| value.pointer(pointer)?.as_str())
+            .map_or_else(|| format!("entry {line}"), str::to_owned);
+        let origin = Origin {
+            file: file.clone(),
+            line,
+            label,
+            note: None,
+        };
+        let entry: trakt::Entry = match serde_json::from_value(value) {
+            Ok(entry) => entry,
+            Err(err) => {
+                read.rejected.push(ReportRow {
+                    origin,
+                    outcome: Outcome::Failed(err.to_string()),
+                });
+                continue;
+            }
+        };
+        let (ty, item) = match (entry.ty, entry.movie, entry.show) {
+            (None, Some(movie), _) => (TitleType::Movie, movie),
+            (Some(ty), Some(movie), _) if ty == "movie" => (TitleType::Movie, movie),
+            (None, None, Some(show)) => (TitleType::Series, show),
+            (Some(ty), _, Some(show)) if ty == "show" => (TitleType::Series, show),
+            (Some(ty), _, _) if ty != "movie" && ty != "show" => {
+                read.rejected.push(ReportRow {
+                    origin,
+                    outcome: Outcome::UnknownType(ty),
+                });
+                continue;
+            }
+            _ => {
+                read.rejected.push(ReportRow {
+                    origin,
+                    outcome: Outcome::Failed("has no movie or show".to_owned()),
+                });
+                continue;
+            }
+        };
+        let imdb = item.ids.imdb.as_deref().and_then(|id| id.parse().ok());
+        let source = match (item.ids.tmdb, imdb) {
+            (Some(id), imdb) => Source::Tmdb {
+                id,
+                ty,
+                imdb,
+                label: match item.year {
+                    Some(year) => format!("{} ({year})", item.title),
+                    None => item.title,
+                },
+            },
+            (None, Some(id)) => Source::Imdb(id),
+            (None, None) => Source::Name {
+                name: item.title,
+                year: item.year,
+            },
+        };
+        read.items.push(ImportItem {
+            source,
+            user: UserFields {
+                my_rating: entry.rating.map(f32::from),
+                watch_count: entry.plays.unwrap_or_default(),
+                last_watched: entry.last_watched_at,
+                status: WatchStatus::Watched,
+            },
+            format: ImportFormat::Trakt,
+            origin,
+        });
+    }
+    read
+}
+
+/// Merges the records of the same title, e.g. the rows of a Letterboxd diary
+/// or a Trakt rating with the watch history from another file, keeping the
+/// order they were first seen in. The records folded into an earlier one are
+/// reported as such.
+pub fn merge_items(items: Vec<ImportItem>, report: &mut ImportReport) -> Vec<ImportItem> {
+    let mut merged: Vec<ImportItem> = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for item in items {
+        match seen.get(&item.source.key()) {
+            Some(&i) => {
+                if item.format.merges() {
+                    merged[i].format = item.format;
+                }
+                merged[i].user.merge(item.user);
+                let into = &merged[i].origin;
+                let outcome = Outcome::Merged {
+                    file: into.file.clone(),
+                    line: into.line,
+                };
+                report.push(item.origin, outcome);
+            }
+            None => {
+                seen.insert(item.source.key(), merged.len());
+                merged.push(item);
+            }
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    /// Writes `contents` to a file called `name` of its own.
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "meepls-import-{}-{}",
+            std::process::id(),
+            name.replace('.', "-")
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn headers(names: &[&str]) -> csv::StringRecord {
+        csv::StringRecord::from(names.to_vec())
+    }
+
+    #[test]
+    fn letterboxd_stars_become_ratings() {
+        assert_eq!(letterboxd_rating(0.5), 1.0);
+        assert_eq!(letterboxd_rating(3.5), 7.0);
+        assert_eq!(letterboxd_rating(5.0), 10.0);
+        assert_eq!(letterboxd_rating(0.0), 1.0);
+    }
+
+    #[test]
+    fn detects_csv_formats() {
+        let detect = |names: &[&str]| ImportFormat::detect(&headers(names));
+        assert_eq!(
+            detect(&["Date", "Name", "Year", "Letterboxd URI", "Rating"]),
+            Some(ImportFormat::Letterboxd)
+        );
+        assert_eq!(
+            detect(&["Const", "Your Rating", "Date Rated", "Title"]),
+            Some(ImportFormat::Imdb)
+        );
+        assert_eq!(
+            detect(&["Position", "Const", "Created", "Title"]),
+            Some(ImportFormat::ImdbWatchlist)
+        );
+        assert_eq!(detect(&["Name", "Year"]), None);
+    }
+
+    #[test]
+    fn reads_letterboxd_ratings() {
+        let path = temp_file(
+            "ratings.csv",
+            "Date,Name,Year,Letterboxd URI,Rating\n\
+             2023-01-02,Heat,1995,https://boxd.it/2bbs,4.5\n\
+             2023-01-03,\"Crouching Tiger, Hidden Dragon\",2000,https://boxd.it/1Z7e,3\n\
+             2023-01-04,Up,soon,https://boxd.it/1ZiW,4\n",
+        );
+        let read = read_file(&path).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let items: Vec<(String, Option<f32>, u64)> = read
+            .items
+            .iter()
+            .map(|item| (item.source.label(), item.user.my_rating, item.origin.line))
+            .collect();
+        assert_eq!(
+            items,
+            [
+                ("Heat (1995)".to_owned(), Some(9.0), 2),
+                (
+                    "Crouching Tiger, Hidden Dragon (2000)".to_owned(),
+                    Some(6.0),
+                    3
+                ),
+            ]
+        );
+        assert!(read
+            .items
+            .iter()
+            .all(|i| i.format == ImportFormat::Letterboxd));
+        let [rejected] = &read.rejected[..] else {
+            panic!("expected one rejected row, got {:?}", read.rejected);
+        };
+        assert_eq!(rejected.origin.label, "Up");
+        assert!(matches!(rejected.outcome, Outcome::Failed(_)));
+        assert!(ImportFormat::Letterboxd.merges());
+    }
+
+    #[test]
+    fn narrower_imdb_types_are_noted() {
+        let path = temp_file(
+            "imdb.csv",
+            "Const,Your Rating,Date Rated,Title,Title Type\n\
+             tt0113277,9,2023-01-02,Heat,movie\n\
+             tt0795176,10,2023-01-03,Planet Earth,tvMiniSeries\n\
+             tt2301451,10,2023-01-04,Ozymandias,tvEpisode\n",
+        );
+        let read = read_file(&path).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let rows: Vec<(String, String)> = read
+            .items
+            .into_iter()
+            .map(|item| ReportRow {
+                origin: item.origin,
+                outcome: Outcome::Imported,
+            })
+            .chain(read.rejected)
+            .map(|row| (row.origin.label.clone(), row.reason()))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("Heat".to_owned(), String::new()),
+                (
+                    "Planet Earth".to_owned(),
+                    "\"tvMiniSeries\" is kept as a TV Series".to_owned()
+                ),
+                (
+                    "Ozymandias".to_owned(),
+                    "\"tvEpisode\" titles can't be imported".to_owned()
+                ),
+            ]
+        );
+    }
+
+    fn diary_row(line: u64, rating: f32) -> ImportItem {
+        ImportItem {
+            source: Source::Name {
+                name: "Heat".to_owned(),
+                year: Some(1995),
+            },
+            user: UserFields {
+                my_rating: Some(rating),
+                ..Default::default()
+            },
+            format: ImportFormat::Letterboxd,
+            origin: Origin {
+                file: "diary.csv".to_owned(),
+                line,
+                label: "Heat (1995)".to_owned(),
+                note: None,
+            },
+        }
+    }
+
+    #[test]
+    fn reads_trakt_exports() {
+        let path = temp_file(
+            "ratings-movies.json",
+            r#"[
+                {
+                    "rated_at": "2023-05-01T20:00:00.000Z",
+                    "rating": 9,
+                    "type": "movie",
+                    "movie": { "title": "Heat", "year": 1995, "ids": { "trakt": 1, "imdb": "tt0113277", "tmdb": 949 } }
+                },
+                {
+                    "rating": 8,
+                    "type": "show",
+                    "show": { "title": "Breaking Bad", "year": 2008, "ids": { "imdb": "tt0903747" } }
+                },
+                {
+                    "rating": 10,
+                    "type": "episode",
+                    "episode": { "season": 5, "number": 14, "title": "Ozymandias" },
+                    "show": { "title": "Breaking Bad", "year": 2008, "ids": { "imdb": "tt0903747", "tmdb": 1396 } }
+                },
+                {
+                    "rating": 7,
+                    "type": "season",
+                    "season": { "number": 1 },
+                    "show": { "title": "The Wire", "year": 2002, "ids": { "tmdb": 1438 } }
+                },
+                { "rating": 6, "type": "movie" },
+                {
+                    "plays": 3,
+                    "last_watched_at": "2023-06-01T21:00:00.000Z",
+                    "movie": { "title": "Up", "year": 2009, "ids": {} }
+                }
+            ]"#,
+        );
+        let read = read_file(&path).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let items: Vec<(String, u64, Option<f32>, u32)> = read
+            .items
+            .iter()
+            .map(|item| {
+                let key = item.source.key();
+                (
+                    key,
+                    item.origin.line,
+                    item.user.my_rating,
+                    item.user.watch_count,
+                )
+            })
+            .collect();
+        assert_eq!(
+            items,
+            [
+                ("tmdb:Movie:949".to_owned(), 1, Some(9.0), 0),
+                ("tt0903747".to_owned(), 2, Some(8.0), 0),
+                ("up:Some(2009)".to_owned(), 6, None, 3),
+            ]
+        );
+        assert!(read.items[2].user.last_watched.is_some());
+
+        let rejected: Vec<(u64, &str, Outcome)> = read
+            .rejected
+            .iter()
+            .map(|row| {
+                (
+                    row.origin.line,
+                    row.origin.label.as_str(),
+                    row.outcome.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rejected,
+            [
+                (
+                    3,
+                    "Breaking Bad",
+                    Outcome::UnknownType("episode".to_owned())
+                ),
+                (4, "The Wire", Outcome::UnknownType("season".to_owned())),
+                (
+                    5,
+                    "entry 5",
+                    Outcome::Failed("has no movie or show".to_owned())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn folded_records_are_reported() {
+        let mut report = ImportReport::default();
+        let items = merge_items(vec![diary_row(2, 8.0), diary_row(5, 9.0)], &mut report);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].user.my_rating, Some(9.0));
+        let [row] = &report.rows[..] else {
+            panic!("expected one row, got {:?}", report.rows);
+        };
+        assert_eq!(row.origin.line, 5);
+        assert_eq!(row.outcome.name(), "Skipped duplicate");
+        assert_eq!(row.outcome.reason(), "merged into line 2 of diary.csv");
+    }
+}
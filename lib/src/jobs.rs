@@ -0,0 +1,449 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fetch::{find_by_name, get_title_from_tmdb, get_title_id_from_imdb};
+use crate::models::{TitleId, TitleType};
+use crate::my_titles::{Title, UserFields};
+use crate::provider::{self, FetchError, MetadataProvider};
+use crate::settings::Settings;
+
+/// Number of fetches that may be in flight at the same time.
+const WORKERS: usize = 4;
+
+pub type JobId = u64;
+
+/// Gives the provider a job is run against, with the settings at the time.
+type MakeProvider = dyn Fn(&Settings, &Job) -> Box<dyn MetadataProvider> + Send + Sync;
+
+/// Refreshes ask TMDB again even if it was asked recently.
+fn default_provider(settings: &Settings, job: &Job) -> Box<dyn MetadataProvider> {
+    match job {
+        Job::Add { .. } => provider::from_settings(settings),
+        Job::Refresh { .. } => provider::fresh_from_settings(settings),
+    }
+}
+
+/// How the title to add is identified.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum Source {
+    Imdb(TitleId),
+    Tmdb {
+        id: i32,
+        ty: TitleType,
+        /// If known, to spot titles already in the library before fetching.
+        imdb: Option<TitleId>,
+        /// Shown until the title is fetched.
+        label: String,
+    },
+    /// Searched for by name and year.
+    Name {
+        name: String,
+        year: Option<u16>,
+    },
+}
+
+impl Source {
+    /// Names the title until it's fetched.
+    pub(crate) fn label(&self) -> String {
+        match self {
+            Source::Imdb(id) => id.to_string(),
+            Source::Tmdb { label, .. } => label.clone(),
+            Source::Name {
+                name,
+                year: Some(year),
+            } => format!("{name} ({year})"),
+            Source::Name { name, year: None } => name.clone(),
+        }
+    }
+
+    /// Tells records of the same title apart from others, as far as possible
+    /// without fetching it.
+    pub(crate) fn key(&self) -> String {
+        match self {
+            Source::Imdb(id) => id.to_string(),
+            Source::Tmdb { id, ty, .. } => format!("tmdb:{ty}:{id}"),
+            Source::Name { name, year } => format!("{}:{year:?}", name.to_lowercase()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum Job {
+    /// Fetch a title and add it to the library.
+    Add { source: Source, user: UserFields },
+    /// Fetch the metadata of a title in the library again.
+    Refresh {
+        id: TitleId,
+        tmdb_id: Option<i32>,
+        ty: TitleType,
+        label: String,
+    },
+}
+
+impl Job {
+    pub(crate) fn label(&self) -> String {
+        match self {
+            Job::Add { source, .. } => source.label(),
+            Job::Refresh { label, .. } => label.clone(),
+        }
+    }
+
+    pub(crate) fn refresh(title: &Title) -> Self {
+        Job::Refresh {
+            id: title.id,
+            tmdb_id: title.tmdb_id,
+            ty: title.ty,
+            label: title.title.clone(),
+        }
+    }
+
+    fn run(self, provider: &dyn MetadataProvider) -> Result<Title, FetchError> {
+        match self {
+            Job::Add { source, user } => {
+                let (id, ty) = match source {
+                    Source::Imdb(id) => get_title_id_from_imdb(provider, id)?,
+                    Source::Tmdb { id, ty, .. } => (id, ty),
+                    Source::Name { name, year } => find_by_name(provider, &name, year)?,
+                };
+                let mut title = get_title_from_tmdb(provider, id, ty, user.my_rating)?;
+                title.watch_count = user.watch_count;
+                title.last_watched = user.last_watched;
+                title.status = user.status;
+                Ok(title)
+            }
+            Job::Refresh {
+                id, tmdb_id, ty, ..
+            } => {
+                let (tmdb_id, ty) = match tmdb_id {
+                    Some(tmdb_id) => (tmdb_id, ty),
+                    None => get_title_id_from_imdb(provider, id)?,
+                };
+                get_title_from_tmdb(provider, tmdb_id, ty, None)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    Queued,
+    InFlight,
+    Done,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct JobEntry {
+    pub label: String,
+    pub status: JobStatus,
+    pub job: Job,
+}
+
+/// How many jobs are in each state.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JobCounts {
+    pub queued: usize,
+    pub in_flight: usize,
+    pub done: usize,
+    pub failed: usize,
+}
+
+impl JobCounts {
+    pub fn total(&self) -> usize {
+        self.queued + self.in_flight + self.done + self.failed
+    }
+
+    pub fn finished(&self) -> bool {
+        self.queued == 0 && self.in_flight == 0
+    }
+}
+
+enum Event {
+    Started(JobId),
+    Finished(JobId, Box<Result<Title, FetchError>>),
+}
+
+/// A job that finished since the last [`JobQueue::poll`].
+pub struct Finished {
+    pub id: JobId,
+    pub label: String,
+    pub job: Job,
+    pub result: Result<Title, FetchError>,
+}
+
+struct Workers {
+    jobs: Sender<(JobId, Job)>,
+    events: Receiver<Event>,
+}
+
+impl Workers {
+    fn spawn(
+        ctx: &egui::Context,
+        settings: &Arc<Mutex<Settings>>,
+        make_provider: &Arc<MakeProvider>,
+    ) -> Self {
+        let (job_tx, job_rx) = channel::<(JobId, Job)>();
+        let (event_tx, event_rx) = channel();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        for _ in 0..WORKERS {
+            let job_rx = Arc::clone(&job_rx);
+            let event_tx = event_tx.clone();
+            let ctx = ctx.clone();
+            let settings = Arc::clone(settings);
+            let make_provider = Arc::clone(make_provider);
+            thread::spawn(move || loop {
+                // Only hold the lock while waiting, so the others can pick up jobs.
+                let Ok((id, job)) = job_rx.lock().unwrap().recv() else {
+                    return;
+                };
+                let send = |event| {
+                    let sent = event_tx.send(event).is_ok();
+                    ctx.request_repaint();
+                    sent
+                };
+                if !send(Event::Started(id)) {
+                    return;
+                }
+                // Picked up per job so changes apply to the jobs still queued.
+                let settings_now = settings.lock().unwrap().effective();
+                let provider = make_provider(&settings_now, &job);
+                let result = job.run(provider.as_ref());
+                if let Err(FetchError::Network(_)) = result {
+                    // Spares the jobs still queued from waiting on the network.
+                    settings.lock().unwrap().unreachable = true;
+                }
+                if !send(Event::Finished(id, Box::new(result))) {
+                    return;
+                }
+            });
+        }
+        Self {
+            jobs: job_tx,
+            events: event_rx,
+        }
+    }
+}
+
+/// Runs fetches on background threads so the UI stays responsive. Results are
+/// picked up by calling [`JobQueue::poll`] every frame.
+pub struct JobQueue {
+    settings: Arc<Mutex<Settings>>,
+    make_provider: Arc<MakeProvider>,
+    workers: Option<Workers>,
+    entries: BTreeMap<JobId, JobEntry>,
+    next_id: JobId,
+}
+
+impl JobQueue {
+    pub fn new(settings: Arc<Mutex<Settings>>) -> Self {
+        Self {
+            settings,
+            make_provider: Arc::new(default_provider),
+            workers: None,
+            entries: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Runs the jobs against the provider `make_provider` gives rather than
+    /// the one the settings and environment call for.
+    #[cfg(test)]
+    fn with_provider(
+        settings: Arc<Mutex<Settings>>,
+        make_provider: impl Fn(&Settings, &Job) -> Box<dyn MetadataProvider> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            make_provider: Arc::new(make_provider),
+            ..Self::new(settings)
+        }
+    }
+
+    pub fn submit(&mut self, ctx: &egui::Context, job: Job) -> JobId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.insert(
+            id,
+            JobEntry {
+                label: job.label(),
+                status: JobStatus::Queued,
+                job: job.clone(),
+            },
+        );
+        let (settings, make_provider) = (&self.settings, &self.make_provider);
+        let workers = self
+            .workers
+            .get_or_insert_with(|| Workers::spawn(ctx, settings, make_provider));
+        workers
+            .jobs
+            .send((id, job))
+            .expect("job workers shouldn't exit while the queue is alive");
+        id
+    }
+
+    /// Collects the jobs that finished since the last call.
+    pub fn poll(&mut self) -> Vec<Finished> {
+        let Some(workers) = &self.workers else {
+            return Vec::new();
+        };
+        let mut finished = Vec::new();
+        for event in workers.events.try_iter() {
+            match event {
+                Event::Started(id) => {
+                    if let Some(entry) = self.entries.get_mut(&id) {
+                        entry.status = JobStatus::InFlight;
+                    }
+                }
+                Event::Finished(id, result) => {
+                    let Some(entry) = self.entries.get_mut(&id) else {
+                        continue;
+                    };
+                    entry.status = match &*result {
+                        Ok(title) => {
+                            entry.label = title.title.clone();
+                            JobStatus::Done
+                        }
+                        Err(err) => JobStatus::Failed(err.to_string()),
+                    };
+                    finished.push(Finished {
+                        id,
+                        label: entry.label.clone(),
+                        job: entry.job.clone(),
+                        result: *result,
+                    });
+                }
+            }
+        }
+        finished
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &JobEntry> {
+        self.entries.values()
+    }
+
+    pub fn counts(&self) -> JobCounts {
+        let mut counts = JobCounts::default();
+        for entry in self.entries.values() {
+            match entry.status {
+                JobStatus::Queued => counts.queued += 1,
+                JobStatus::InFlight => counts.in_flight += 1,
+                JobStatus::Done => counts.done += 1,
+                JobStatus::Failed(_) => counts.failed += 1,
+            }
+        }
+        counts
+    }
+
+    /// Forgets about jobs that are done or failed.
+    pub fn clear_finished(&mut self) {
+        self.entries
+            .retain(|_, e| matches!(e.status, JobStatus::Queued | JobStatus::InFlight));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::http::{HttpClient, HttpConfig};
+    use crate::provider::{FixtureProvider, TmdbProvider};
+    use crate::stub::{StubServer, TEST_FIXTURES};
+
+    fn add(id: u32) -> Job {
+        Job::Add {
+            source: Source::Imdb(TitleId(id)),
+            user: UserFields::default(),
+        }
+    }
+
+    fn tmdb_at(url: String) -> TmdbProvider {
+        let settings = Settings {
+            credential: "0123456789abcdef".to_owned(),
+            ..Default::default()
+        };
+        let client = HttpClient::new(HttpConfig {
+            max_retries: 0,
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        });
+        TmdbProvider::new(settings)
+            .with_base_url(url)
+            .with_client(Arc::new(client))
+    }
+
+    /// Polls `queue` until every job finished.
+    fn wait(queue: &mut JobQueue) -> Vec<Finished> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut finished = Vec::new();
+        while !queue.counts().finished() {
+            assert!(Instant::now() < deadline, "jobs didn't finish");
+            thread::sleep(Duration::from_millis(10));
+            finished.extend(queue.poll());
+        }
+        finished.sort_by_key(|f| f.id);
+        finished
+    }
+
+    fn runs_jobs(queue: &mut JobQueue) {
+        let ctx = egui::Context::default();
+        let leon = queue.submit(&ctx, add(110413));
+        let missing = queue.submit(&ctx, add(1));
+        let counts = queue.counts();
+        assert_eq!(counts.total(), 2);
+        assert!(!counts.finished());
+
+        let finished = wait(queue);
+        assert_eq!(finished.len(), 2);
+        assert_eq!(finished[0].id, leon);
+        assert_eq!(finished[0].label, "Léon: The Professional");
+        assert_eq!(finished[0].result.as_ref().unwrap().id, TitleId(110413));
+        assert_eq!(finished[1].id, missing);
+        assert!(finished[1].result.is_err());
+
+        let counts = queue.counts();
+        assert_eq!((counts.done, counts.failed), (1, 1));
+        queue.clear_finished();
+        assert_eq!(queue.counts().total(), 0);
+    }
+
+    #[test]
+    fn runs_jobs_against_fixtures() {
+        let settings = Arc::new(Mutex::new(Settings::default()));
+        let mut queue = JobQueue::with_provider(settings, |_, _| {
+            Box::new(FixtureProvider::new(TEST_FIXTURES))
+        });
+        runs_jobs(&mut queue);
+    }
+
+    #[test]
+    fn runs_jobs_against_the_stub() {
+        let server = StubServer::start(TEST_FIXTURES).unwrap();
+        let url = server.url();
+        let settings = Arc::new(Mutex::new(Settings::default()));
+        let mut queue =
+            JobQueue::with_provider(settings, move |_, _| Box::new(tmdb_at(url.clone())));
+        runs_jobs(&mut queue);
+    }
+
+    #[test]
+    fn network_errors_mark_tmdb_unreachable() {
+        // Nothing listens on the port of a stopped server.
+        let url = StubServer::start(TEST_FIXTURES).unwrap().url();
+        let settings = Arc::new(Mutex::new(Settings::default()));
+        let mut queue = JobQueue::with_provider(Arc::clone(&settings), move |_, _| {
+            Box::new(tmdb_at(url.clone()))
+        });
+        queue.submit(&egui::Context::default(), add(110413));
+        let finished = wait(&mut queue);
+        assert!(finished[0].result.as_ref().unwrap_err().is_offline());
+        assert!(settings.lock().unwrap().unreachable);
+    }
+}
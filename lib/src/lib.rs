@@ -1,20 +1,59 @@
-mod models;
+macro_rules! ok_or {
+    ($e:expr, $err:expr) => {{
+        match $e {
+            Ok(r) => r,
+            Err(_) => $err,
+        }
+    }};
+}
+
+mod cache;
+mod export;
+mod fetch;
+mod filter;
+pub mod http;
+mod import;
+mod jobs;
+mod library;
+pub mod models;
 mod my_titles;
+pub mod provider;
 mod recommend;
+mod settings;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod store;
+#[cfg(any(test, feature = "stub"))]
+pub mod stub;
 
 use std::{
     ops::DerefMut,
     sync::{Arc, Mutex},
 };
 
-pub use my_titles::MyTitlesApp;
-use my_titles::Titles;
-pub use recommend::RecommendApp;
+pub use cache::{DiskCache, PosterLoader};
+pub use filter::{FilterError, SortKey, TitleFilter};
+pub use import::{ImportFormat, ImportReport, Origin, Outcome};
+use jobs::Job;
+pub use library::{Library, LibraryError};
+pub use my_titles::{MyTitlesApp, Title};
+use my_titles::{TitleList, Titles};
+pub use recommend::{Factor, FactorKind, RecommendApp, Recommendation};
+pub use settings::{Settings, SettingsApp};
+pub use store::{
+    load_state, open_store, save_state, EframeStore, FileStorage, StateStore, StoreError,
+    STORE_VERSION,
+};
+
+/// Name of the app's data directory, and of its window.
+pub const APP_ID: &str = "meepls";
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
 pub enum Anchor {
+    #[default]
     MyTitles,
     Recommend,
+    Settings,
 }
 
 impl std::fmt::Display for Anchor {
@@ -29,22 +68,26 @@ impl From<Anchor> for egui::WidgetText {
     }
 }
 
-impl Default for Anchor {
-    fn default() -> Self {
-        Self::MyTitles
-    }
-}
 #[derive(Default, Debug, serde::Deserialize, serde::Serialize)]
 pub struct ToStore {
     pub selected_anchor: Anchor,
     pub titles: Titles,
+    #[serde(default)]
+    pub lists: Vec<TitleList>,
+    #[serde(default)]
+    pub filter: TitleFilter,
+    #[serde(default)]
+    pub queued: Vec<Job>,
     pub recommend: RecommendApp,
+    #[serde(default)]
+    pub settings: Settings,
 }
 
 pub struct State {
     pub selected_anchor: Anchor,
     pub my_titles: MyTitlesApp,
     pub recommend: RecommendApp,
+    pub settings: SettingsApp,
     pub toasts: Arc<Mutex<egui_notify::Toasts>>,
 }
 
@@ -60,6 +103,14 @@ impl State {
             }
         }
         self.selected_anchor = selected_anchor;
+        ui.separator();
+        let mut settings = self.settings.settings.lock().unwrap();
+        ui.toggle_value(&mut settings.work_offline, "📴 Offline")
+            .on_hover_text("Only use the library and the cache");
+        if settings.unreachable && !settings.work_offline {
+            ui.colored_label(ui.visuals().warn_fg_color, "TMDB can't be reached");
+        }
+        drop(settings);
         ui.with_layout(egui::Layout::bottom_up(egui::Align::LEFT), |ui| {
             egui::warn_if_debug_build(ui);
         });
@@ -77,11 +128,20 @@ impl State {
                 Anchor::Recommend,
                 &mut self.recommend as &mut dyn eframe::App,
             ),
+            (
+                "Settings",
+                Anchor::Settings,
+                &mut self.settings as &mut dyn eframe::App,
+            ),
         ];
         vec.into_iter()
     }
 
     fn show_selected_app(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        self.my_titles.sync_connection(ctx);
+        self.my_titles.refresh_stale(ctx);
+        self.recommend
+            .sync(ctx, &self.my_titles.titles, self.my_titles.revision);
         let selected_anchor = self.selected_anchor;
         for (_name, anchor, app) in self.apps_iter_mut() {
             if anchor == selected_anchor || ctx.memory(|mem| mem.everything_is_visible()) {
@@ -0,0 +1,424 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use thiserror::Error;
+
+use crate::export::{write_imdb_csv_to, write_json_to, ExportError};
+use crate::filter::TitleFilter;
+use crate::import::{
+    merge_items, read_file, ImportError, ImportFormat, ImportReport, Origin, Outcome,
+};
+use crate::jobs::{Job, JobId, JobQueue, Source};
+use crate::models::{TitleId, WatchStatus};
+use crate::my_titles::{import_lists, insert_title, owned, Insert, Title, UserFields};
+use crate::recommend::Recommendation;
+use crate::settings::Settings;
+use crate::store::{open_store, FileStorage, StateStore, StoreError};
+use crate::ToStore;
+
+/// How often to look for finished jobs while waiting on them.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Error, Debug)]
+pub enum LibraryError {
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    #[error("couldn't import: {0}")]
+    Import(#[from] ImportError),
+    #[error("couldn't export: {0}")]
+    Export(#[from] ExportError),
+    #[error("{0} isn't in the library")]
+    NotInLibrary(TitleId),
+    #[error("{file} is a {found} export, not the format asked for")]
+    WrongFormat { file: String, found: &'static str },
+    #[error("ratings go from 1 to 10 in steps of 0.5, {0} isn't one")]
+    BadRating(f32),
+    #[error("working offline, TMDB can't be asked")]
+    Offline,
+}
+
+/// The library as the app stores it, without the app, for scripting. Fetches
+/// go through the same job workers and cache, and block until done. Nothing
+/// is written until [`Library::save`].
+pub struct Library {
+    storage: FileStorage,
+    store: Box<dyn StateStore>,
+    state: ToStore,
+    settings: Arc<Mutex<Settings>>,
+    jobs: JobQueue,
+    /// Only there for the job workers to request repaints of.
+    ctx: egui::Context,
+}
+
+impl Library {
+    /// Loads the library from the data directory.
+    pub fn open() -> Result<Self, LibraryError> {
+        let storage = FileStorage::in_data_dir()?;
+        let mut store = open_store()?;
+        let mut state = store.load(&storage)?.unwrap_or_default();
+        let settings = Arc::new(Mutex::new(std::mem::take(&mut state.settings)));
+        state.recommend.settings = Arc::clone(&settings);
+        Ok(Self {
+            jobs: JobQueue::new(Arc::clone(&settings)),
+            storage,
+            store,
+            state,
+            settings,
+            ctx: egui::Context::default(),
+        })
+    }
+
+    pub fn save(self) -> Result<(), LibraryError> {
+        let Self {
+            mut storage,
+            mut store,
+            mut state,
+            settings,
+            ..
+        } = self;
+        state.settings = settings.lock().unwrap().clone();
+        store.save(&mut storage, state)?;
+        storage.write()?;
+        Ok(())
+    }
+
+    /// What went wrong loading without failing it, see [`StateStore::take_warning`].
+    pub fn take_warning(&mut self) -> Option<StoreError> {
+        self.store.take_warning()
+    }
+
+    pub fn title(&self, id: TitleId) -> Option<&Title> {
+        self.state.titles.get(&id)
+    }
+
+    /// The titles `filter` and `status` let through, sorted by `filter`.
+    pub fn titles(&self, filter: &TitleFilter, status: Option<WatchStatus>) -> Vec<&Title> {
+        let mut titles: Vec<&Title> = self
+            .state
+            .titles
+            .values()
+            .filter(|t| (status.is_none() || status == Some(t.status)) && filter.matches(t))
+            .collect();
+        filter.sort(&mut titles);
+        titles
+    }
+
+    /// Adds that couldn't reach TMDB, waiting for the next [`Library::add_queued`].
+    pub fn queued(&self) -> usize {
+        self.state.queued.len()
+    }
+
+    /// Submits `jobs` and waits for them, recording what became of each in
+    /// the row of `report` it comes with.
+    fn run(&mut self, jobs: Vec<(Job, usize)>, report: &mut ImportReport) {
+        let mut rows: HashMap<JobId, usize> = jobs
+            .into_iter()
+            .map(|(job, row)| (self.jobs.submit(&self.ctx, job), row))
+            .collect();
+        while !rows.is_empty() {
+            thread::sleep(POLL_INTERVAL);
+            for finished in self.jobs.poll() {
+                let Some(row) = rows.remove(&finished.id) else {
+                    continue;
+                };
+                let outcome = match (finished.job, finished.result) {
+                    (Job::Refresh { id, .. }, Ok(fetched)) => {
+                        match self.state.titles.get_mut(&id) {
+                            Some(title) => {
+                                title.refresh_from(fetched);
+                                Outcome::Updated
+                            }
+                            None => Outcome::Failed("no longer in the library".to_owned()),
+                        }
+                    }
+                    (Job::Add { .. }, Ok(title)) => {
+                        match insert_title(&mut self.state.titles, title) {
+                            Insert::Added => Outcome::Imported,
+                            Insert::Duplicate => Outcome::Duplicate,
+                        }
+                    }
+                    (job @ Job::Add { .. }, Err(err)) if err.is_offline() => {
+                        self.state.queued.push(job);
+                        Outcome::Queued
+                    }
+                    (_, Err(err)) => Outcome::Failed(err.to_string()),
+                };
+                report.rows[row].outcome = outcome;
+            }
+        }
+        self.jobs.clear_finished();
+    }
+
+    /// Adds the title with the IMDb id `input`, or else the one called that,
+    /// as long as the name leaves no doubt.
+    pub fn add(&mut self, input: &str, year: Option<u16>, status: WatchStatus) -> ImportReport {
+        let input = input.trim();
+        // Names like "1917" or "Léon" aren't ids, only `tt` and digits are.
+        let source = match input.parse() {
+            Ok(id) => Source::Imdb(id),
+            Err(_) => Source::Name {
+                name: input.to_owned(),
+                year,
+            },
+        };
+        let origin = Origin {
+            file: String::new(),
+            line: 0,
+            label: source.label(),
+            note: None,
+        };
+        let mut report = ImportReport::default();
+        if owned(&self.state.titles, &source).is_some() {
+            report.push(origin, Outcome::Duplicate);
+            return report;
+        }
+        let row = report.push(origin, Outcome::Pending);
+        let user = UserFields {
+            status,
+            ..Default::default()
+        };
+        self.run(vec![(Job::Add { source, user }, row)], &mut report);
+        report
+    }
+
+    /// Imports a file the way the Import button does. With `formats`, fails
+    /// unless the file is in one of them.
+    pub fn import(
+        &mut self,
+        path: &Path,
+        formats: Option<&[ImportFormat]>,
+    ) -> Result<ImportReport, LibraryError> {
+        let read = read_file(path)?;
+        if let Some(formats) = formats {
+            let found = match read.items.iter().find(|i| !formats.contains(&i.format)) {
+                Some(item) => Some(item.format.name()),
+                None if !read.titles.is_empty() => Some("meepls library"),
+                None => None,
+            };
+            if let Some(found) = found {
+                return Err(LibraryError::WrongFormat {
+                    file: path.display().to_string(),
+                    found,
+                });
+            }
+        }
+        let mut report = ImportReport::default();
+        report.rows.extend(read.rejected);
+        import_lists(&mut self.state.lists, read.lists);
+        for (origin, title) in read.titles {
+            let outcome = match insert_title(&mut self.state.titles, title) {
+                Insert::Added => Outcome::Imported,
+                Insert::Duplicate => Outcome::Duplicate,
+            };
+            report.push(origin, outcome);
+        }
+        let mut jobs = Vec::new();
+        for item in merge_items(read.items, &mut report) {
+            let owned = owned(&self.state.titles, &item.source);
+            match owned.and_then(|id| self.state.titles.get_mut(&id)) {
+                Some(title) => {
+                    let outcome = if item.format.merges() && item.user.merge_into(title) {
+                        Outcome::Updated
+                    } else {
+                        Outcome::Duplicate
+                    };
+                    report.push(item.origin, outcome);
+                }
+                None => {
+                    let row = report.push(item.origin, Outcome::Pending);
+                    let job = Job::Add {
+                        source: item.source,
+                        user: item.user,
+                    };
+                    jobs.push((job, row));
+                }
+            }
+        }
+        self.run(jobs, &mut report);
+        Ok(report)
+    }
+
+    pub fn write_json(&self, writer: impl Write) -> Result<(), LibraryError> {
+        Ok(write_json_to(
+            &self.state.titles,
+            &self.state.lists,
+            writer,
+        )?)
+    }
+
+    pub fn write_imdb_csv(&self, writer: impl Write) -> Result<(), LibraryError> {
+        Ok(write_imdb_csv_to(
+            &self.state.titles,
+            &self.state.lists,
+            writer,
+        )?)
+    }
+
+    /// Sets or, with `None`, clears the user's rating of `id`.
+    pub fn rate(&mut self, id: TitleId, rating: Option<f32>) -> Result<&Title, LibraryError> {
+        if let Some(rating) = rating {
+            if !(1.0..=10.0).contains(&rating) || (rating * 2.0).fract() != 0.0 {
+                return Err(LibraryError::BadRating(rating));
+            }
+        }
+        let title = self
+            .state
+            .titles
+            .get_mut(&id)
+            .ok_or(LibraryError::NotInLibrary(id))?;
+        title.rate(rating);
+        Ok(title)
+    }
+
+    /// Titles whose metadata is older than the settings allow, oldest first.
+    pub fn stale(&self) -> Vec<TitleId> {
+        let days = self.settings.lock().unwrap().refresh_after_days;
+        if days == 0 {
+            return Vec::new();
+        }
+        let max_age = chrono::Duration::days(days.into());
+        let mut stale: Vec<&Title> = self
+            .state
+            .titles
+            .values()
+            .filter(|t| t.is_stale(max_age))
+            .collect();
+        stale.sort_by_key(|t| t.fetched_at);
+        stale.iter().map(|t| t.id).collect()
+    }
+
+    /// Fetches the metadata of `ids` again, keeping what the user recorded.
+    pub fn refresh(
+        &mut self,
+        ids: impl IntoIterator<Item = TitleId>,
+    ) -> Result<ImportReport, LibraryError> {
+        if self.settings.lock().unwrap().work_offline {
+            return Err(LibraryError::Offline);
+        }
+        let mut report = ImportReport::default();
+        let mut jobs = Vec::new();
+        for id in ids {
+            let title = self.title(id).ok_or(LibraryError::NotInLibrary(id))?;
+            let job = Job::refresh(title);
+            let origin = Origin {
+                file: String::new(),
+                line: 0,
+                label: title.title.clone(),
+                note: None,
+            };
+            jobs.push((job, report.push(origin, Outcome::Pending)));
+        }
+        self.run(jobs, &mut report);
+        Ok(report)
+    }
+
+    /// Tries the adds queued while TMDB couldn't be reached again, the ones
+    /// that still can't are queued anew.
+    pub fn add_queued(&mut self) -> Result<ImportReport, LibraryError> {
+        if self.settings.lock().unwrap().work_offline {
+            return Err(LibraryError::Offline);
+        }
+        let mut report = ImportReport::default();
+        let jobs = std::mem::take(&mut self.state.queued)
+            .into_iter()
+            .map(|job| {
+                let origin = Origin {
+                    file: String::new(),
+                    line: 0,
+                    label: job.label(),
+                    note: None,
+                };
+                (job, report.push(origin, Outcome::Pending))
+            })
+            .collect();
+        self.run(jobs, &mut report);
+        Ok(report)
+    }
+
+    /// The best `limit` recommendations. With `find_new`, the candidates are
+    /// looked up on TMDB again first, as "Find new titles" does.
+    pub fn recommend(
+        &mut self,
+        limit: usize,
+        find_new: bool,
+    ) -> Result<Vec<(&Title, &Recommendation)>, LibraryError> {
+        if find_new {
+            if self.settings.lock().unwrap().work_offline {
+                return Err(LibraryError::Offline);
+            }
+            self.state.recommend.refresh_candidates(&self.state.titles);
+        } else {
+            self.state.recommend.rank(&self.state.titles);
+        }
+        Ok(self.state.recommend.recommended().take(limit).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::EframeStore;
+
+    /// A library of `titles` kept in memory, never saved.
+    fn library(titles: impl IntoIterator<Item = Title>) -> Library {
+        let file = std::env::temp_dir().join(format!("meepls-library-{}.ron", std::process::id()));
+        let settings = Arc::new(Mutex::new(Settings::default()));
+        Library {
+            storage: FileStorage::open(file).unwrap(),
+            store: Box::new(EframeStore),
+            state: ToStore {
+                titles: titles.into_iter().map(|t| (t.id, t)).collect(),
+                ..Default::default()
+            },
+            jobs: JobQueue::new(Arc::clone(&settings)),
+            settings,
+            ctx: egui::Context::default(),
+        }
+    }
+
+    #[test]
+    fn rates_in_half_steps() {
+        let heat = TitleId(113277);
+        let mut library = library([Title::test(heat.0, "Heat")]);
+        assert_eq!(library.rate(heat, Some(7.5)).unwrap().my_rating, Some(7.5));
+        assert_eq!(
+            library.rate(heat, Some(10.0)).unwrap().my_rating,
+            Some(10.0)
+        );
+        assert_eq!(library.rate(heat, None).unwrap().my_rating, None);
+        let history: Vec<Option<f32>> = library
+            .title(heat)
+            .unwrap()
+            .rating_history
+            .iter()
+            .map(|edit| edit.rating)
+            .collect();
+        assert_eq!(history, [Some(7.5), Some(10.0), None]);
+    }
+
+    #[test]
+    fn rejects_ratings_off_the_scale() {
+        let heat = TitleId(113277);
+        let mut library = library([Title::test(heat.0, "Heat")]);
+        for rating in [0.5, 10.5, 7.3, -1.0, f32::NAN] {
+            assert!(
+                matches!(
+                    library.rate(heat, Some(rating)),
+                    Err(LibraryError::BadRating(_))
+                ),
+                "{rating} was taken"
+            );
+        }
+        assert_eq!(library.title(heat).unwrap().my_rating, None);
+        assert!(matches!(
+            library.rate(TitleId(903747), Some(8.0)),
+            Err(LibraryError::NotInLibrary(TitleId(903747)))
+        ));
+    }
+}
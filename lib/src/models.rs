@@ -3,7 +3,7 @@ use std::fmt;
 use std::str::FromStr;
 use thiserror::Error;
 
-#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub struct TitleId(pub u32);
 
 impl fmt::Display for TitleId {
@@ -19,11 +19,13 @@ pub struct ParseTitleIdError;
 impl FromStr for TitleId {
     type Err = ParseTitleIdError;
 
+    /// Takes `tt` followed by digits only, as IMDb writes them.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.len() < 3 {
+        let digits = s.strip_prefix("tt").ok_or(ParseTitleIdError)?;
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
             return Err(ParseTitleIdError);
         }
-        Ok(TitleId(s[2..].parse().map_err(|_| ParseTitleIdError)?))
+        Ok(TitleId(digits.parse().map_err(|_| ParseTitleIdError)?))
     }
 }
 
@@ -46,7 +48,7 @@ impl<'de> Deserialize<'de> for TitleId {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Hash, Eq, PartialEq)]
 pub enum TitleType {
     Movie,
     Series,
@@ -61,10 +63,45 @@ impl fmt::Display for TitleType {
     }
 }
 
+/// Where the user is with a title.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default, Hash, Eq, PartialEq)]
+pub enum WatchStatus {
+    /// Wants to watch it.
+    Watchlist,
+    Watching,
+    #[default]
+    Watched,
+    /// Started it and gave up.
+    Dropped,
+}
+
+impl WatchStatus {
+    pub const ALL: [Self; 4] = [
+        WatchStatus::Watchlist,
+        WatchStatus::Watching,
+        WatchStatus::Watched,
+        WatchStatus::Dropped,
+    ];
+}
+
+impl fmt::Display for WatchStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchStatus::Watchlist => write!(f, "Watchlist"),
+            WatchStatus::Watching => write!(f, "Watching"),
+            WatchStatus::Watched => write!(f, "Watched"),
+            WatchStatus::Dropped => write!(f, "Dropped"),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum CsvTitleType {
     Movie,
     Series,
+    MiniSeries,
+    Short,
+    TvMovie,
 }
 
 #[derive(Error, Debug)]
@@ -78,16 +115,26 @@ impl FromStr for CsvTitleType {
         match s {
             "movie" => Ok(CsvTitleType::Movie),
             "tvSeries" => Ok(CsvTitleType::Series),
+            "tvMiniSeries" => Ok(CsvTitleType::MiniSeries),
+            "short" => Ok(CsvTitleType::Short),
+            "tvMovie" => Ok(CsvTitleType::TvMovie),
             _ => Err(ParseTitleError),
         }
     }
 }
 
+impl CsvTitleType {
+    /// Whether the app files it under a broader type, losing the difference.
+    pub fn is_narrower(self) -> bool {
+        !matches!(self, CsvTitleType::Movie | CsvTitleType::Series)
+    }
+}
+
 impl From<CsvTitleType> for TitleType {
     fn from(value: CsvTitleType) -> Self {
         match value {
-            CsvTitleType::Movie => TitleType::Movie,
-            CsvTitleType::Series => TitleType::Series,
+            CsvTitleType::Movie | CsvTitleType::Short | CsvTitleType::TvMovie => TitleType::Movie,
+            CsvTitleType::Series | CsvTitleType::MiniSeries => TitleType::Series,
         }
     }
 }
@@ -102,11 +149,32 @@ impl<'de> Deserialize<'de> for CsvTitleType {
     }
 }
 
+/// A row of IMDb's ratings or watchlist export.
 #[derive(Deserialize, Debug)]
 pub struct ImdbCsvRow {
     #[serde(rename = "Const")]
     pub id: TitleId,
-    #[serde(rename = "Your Rating")]
+    /// Missing in older watchlist exports.
+    #[serde(rename = "Your Rating", default)]
+    pub rating: Option<f32>,
+    #[serde(rename = "Title", default)]
+    pub title: String,
+    /// Parsed on its own, so rows of other types can be reported as such.
+    #[serde(rename = "Title Type", default)]
+    pub ty: Option<String>,
+}
+
+/// A row of Letterboxd's `ratings.csv`, `watched.csv` or `diary.csv`.
+#[derive(Deserialize, Debug)]
+pub struct LetterboxdCsvRow {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Year")]
+    pub year: Option<u16>,
+    #[serde(rename = "Letterboxd URI")]
+    pub uri: String,
+    /// 0.5 to 5 stars, missing in `watched.csv`.
+    #[serde(rename = "Rating", default)]
     pub rating: Option<f32>,
 }
 
@@ -132,14 +200,56 @@ pub mod tmdb {
         pub release_date: String,
         #[serde(rename = "vote_average")]
         pub rating: f32,
-        pub imdb_id: String,
+        /// Only present on movies, series have it in `external_ids`.
+        pub imdb_id: Option<String>,
+        pub external_ids: Option<ExternalIds>,
         pub credits: Credits,
         pub genres: Vec<Genre>,
-        pub poster_path: String,
+        pub poster_path: Option<String>,
         pub overview: String,
         pub created_by: Option<Vec<Cast>>,
     }
 
+    impl DetailWCredits {
+        pub fn imdb_id(&self) -> Option<&str> {
+            self.imdb_id
+                .as_deref()
+                .or_else(|| self.external_ids.as_ref()?.imdb_id.as_deref())
+        }
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct ExternalIds {
+        pub imdb_id: Option<String>,
+    }
+
+    /// A paginated list response.
+    #[derive(Deserialize, Debug)]
+    pub struct Page<T> {
+        pub results: Vec<T>,
+    }
+
+    /// An entry of `/movie/{id}/recommendations`, `/tv/{id}/similar` and friends.
+    #[derive(Deserialize, Debug)]
+    pub struct RelatedTitle {
+        pub id: i32,
+    }
+
+    /// An entry of `/search/multi`.
+    #[derive(Deserialize, Debug)]
+    pub struct SearchResult {
+        pub id: i32,
+        /// `movie`, `tv` or `person`.
+        pub media_type: String,
+        #[serde(alias = "name", default)]
+        pub title: String,
+        #[serde(alias = "first_air_date", default)]
+        pub release_date: Option<String>,
+        pub poster_path: Option<String>,
+        #[serde(default)]
+        pub overview: String,
+    }
+
     // #[derive(Deserialize, Debug)]
     // pub struct SeriesDetailResponse {
     //     pub name: String,
@@ -174,3 +284,52 @@ pub mod tmdb {
         pub job: String,
     }
 }
+
+/// The parts of a Trakt data export the app uses.
+pub mod trakt {
+    use chrono::{DateTime, Utc};
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug)]
+    pub struct Ids {
+        pub imdb: Option<String>,
+        pub tmdb: Option<i32>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Item {
+        pub title: String,
+        pub year: Option<u16>,
+        pub ids: Ids,
+    }
+
+    /// An entry of `watched-*.json` or `ratings-*.json`. Episode and season
+    /// ratings also carry their show, they're told apart by `ty`.
+    #[derive(Deserialize, Debug)]
+    pub struct Entry {
+        /// Only in ratings.
+        #[serde(rename = "type")]
+        pub ty: Option<String>,
+        pub movie: Option<Item>,
+        pub show: Option<Item>,
+        /// Only in watched history.
+        pub plays: Option<u32>,
+        pub last_watched_at: Option<DateTime<Utc>>,
+        /// 1 to 10.
+        pub rating: Option<u8>,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_id_needs_tt_and_digits() {
+        assert_eq!("tt0110413".parse::<TitleId>().unwrap(), TitleId(110413));
+        assert_eq!(TitleId(110413).to_string(), "tt0110413");
+        for input in ["Léon", "1917", "2012", "300", "tt", "tt+12", "tt12a", ""] {
+            assert!(input.parse::<TitleId>().is_err(), "{input} parsed");
+        }
+    }
+}
@@ -0,0 +1,537 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{mpsc::channel, Arc},
+    time::Duration,
+};
+
+use thiserror::Error;
+
+use crate::cache::{sha256, DiskCache, API_TTL};
+use crate::http::HttpClient;
+use crate::models::{tmdb, TitleId, TitleType};
+use crate::settings::{env_override, Auth, Settings};
+
+/// Directory of recorded responses to serve instead of asking TMDB.
+const FIXTURES_ENV: &str = "MEEPLS_FIXTURES";
+/// Directory to record TMDB responses into, in the layout [`FixtureProvider`] reads.
+const RECORD_ENV: &str = "MEEPLS_RECORD_FIXTURES";
+
+#[derive(Error, Debug)]
+pub enum FetchError {
+    #[error("couldn't reach TMDB: {0}")]
+    Network(String),
+    #[error("TMDB responded with {status} {status_text}")]
+    Status { status: u16, status_text: String },
+    #[error("unexpected response from TMDB: {0}")]
+    Decode(#[from] serde_json::Error),
+    #[error("TMDB response has no {0}")]
+    MissingField(&'static str),
+    #[error("{0} isn't on TMDB")]
+    NotFound(TitleId),
+    #[error("{0} matches more than one title on TMDB")]
+    Ambiguous(TitleId),
+    #[error("no title on TMDB matches {0}")]
+    NoMatch(String),
+    #[error("{0} matches more than one title on TMDB")]
+    AmbiguousMatch(String),
+    #[error("offline, and the answer isn't cached")]
+    Offline,
+    #[error("set a TMDB credential in Settings or MEEPLS_TMDB_CREDENTIAL")]
+    NoCredential,
+}
+
+impl FetchError {
+    /// Whether TMDB wasn't reached at all, so trying again later may work.
+    pub fn is_offline(&self) -> bool {
+        matches!(self, FetchError::Network(_) | FetchError::Offline)
+    }
+}
+
+/// The TMDB lists of titles related to a given one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Related {
+    Recommendations,
+    Similar,
+}
+
+impl Related {
+    fn path(self) -> &'static str {
+        match self {
+            Related::Recommendations => "recommendations",
+            Related::Similar => "similar",
+        }
+    }
+}
+
+/// Where title metadata comes from.
+pub trait MetadataProvider: Send + Sync {
+    /// Recorded with the titles fetched from it.
+    fn name(&self) -> &'static str;
+
+    /// Looks up the TMDB entries for an IMDb id.
+    fn find_by_imdb(&self, id: TitleId) -> Result<tmdb::FindById, FetchError>;
+
+    /// Details of a title, including its credits and external ids.
+    fn details(&self, id: i32, typ: TitleType) -> Result<tmdb::DetailWCredits, FetchError>;
+
+    /// First page of the titles `related` to a title.
+    fn related(
+        &self,
+        id: i32,
+        typ: TitleType,
+        related: Related,
+    ) -> Result<tmdb::Page<tmdb::RelatedTitle>, FetchError>;
+
+    /// First page of movies, series and people matching `query`.
+    fn search(&self, query: &str) -> Result<tmdb::Page<tmdb::SearchResult>, FetchError>;
+}
+
+/// The provider to use with `settings`: recorded responses if `MEEPLS_FIXTURES`
+/// is set, TMDB otherwise.
+pub fn from_settings(settings: &Settings) -> Box<dyn MetadataProvider> {
+    with_max_age(settings, API_TTL)
+}
+
+/// Like [`from_settings`], but TMDB is asked again even if it was asked
+/// recently. The cache is still used when offline.
+pub fn fresh_from_settings(settings: &Settings) -> Box<dyn MetadataProvider> {
+    with_max_age(settings, Duration::ZERO)
+}
+
+fn with_max_age(settings: &Settings, max_age: Duration) -> Box<dyn MetadataProvider> {
+    match env_override(FIXTURES_ENV) {
+        Some(dir) => Box::new(FixtureProvider::new(dir)),
+        None => {
+            let mut provider = TmdbProvider::new(settings.clone());
+            provider.record_dir = env_override(RECORD_ENV).map(PathBuf::from);
+            provider.cache = DiskCache::in_data_dir();
+            provider.max_age = max_age;
+            provider.offline = settings.is_offline();
+            Box::new(provider)
+        }
+    }
+}
+
+/// Percent-encodes `s` for use in a query string.
+pub(crate) fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn encode_query(query: &[(&str, &str)]) -> String {
+    query
+        .iter()
+        .map(|(k, v)| format!("{k}={}", encode(v)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn type_path(typ: TitleType) -> &'static str {
+    match typ {
+        TitleType::Movie => "movie",
+        TitleType::Series => "tv",
+    }
+}
+
+/// A request to the TMDB API, regardless of who answers it.
+struct Endpoint {
+    path: String,
+    params: Vec<(&'static str, String)>,
+}
+
+impl Endpoint {
+    fn find(id: TitleId) -> Self {
+        Self {
+            path: format!("/find/{id}"),
+            params: vec![("external_source", "imdb_id".to_owned())],
+        }
+    }
+
+    fn details(id: i32, typ: TitleType) -> Self {
+        let append = match typ {
+            TitleType::Movie => "credits",
+            // Series only carry their IMDb id in the external ids.
+            TitleType::Series => "credits,external_ids",
+        };
+        Self {
+            path: format!("/{}/{id}", type_path(typ)),
+            params: vec![("append_to_response", append.to_owned())],
+        }
+    }
+
+    fn related(id: i32, typ: TitleType, related: Related) -> Self {
+        Self {
+            path: format!("/{}/{id}/{}", type_path(typ), related.path()),
+            params: vec![("page", "1".to_owned())],
+        }
+    }
+
+    fn search(query: &str) -> Self {
+        Self {
+            path: "/search/multi".to_owned(),
+            params: vec![("query", query.to_owned()), ("page", "1".to_owned())],
+        }
+    }
+
+    /// Where the response to this request is recorded, relative to the fixture
+    /// directory.
+    fn fixture_path(&self) -> PathBuf {
+        let query = self
+            .params
+            .iter()
+            .find(|(k, _)| *k == "query")
+            .map(|(_, v)| encode(v));
+        fixture_path(&self.path, query.as_deref())
+    }
+}
+
+/// `/movie/949` is recorded at `movie/949.json`, searches at
+/// `search/multi/<encoded query>.json`. The extension is appended rather than
+/// set, queries like "Mr. Nobody" have dots of their own.
+pub(crate) fn fixture_path(path: &str, encoded_query: Option<&str>) -> PathBuf {
+    let mut file = PathBuf::from(path.trim_start_matches('/'));
+    if let Some(query) = encoded_query {
+        file.push(query);
+    }
+    let mut file = file.into_os_string();
+    file.push(".json");
+    file.into()
+}
+
+/// How long [`is_reachable`] waits for an answer.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether anything answers at `base_url` within [`PROBE_TIMEOUT`], whatever
+/// the answer.
+pub(crate) fn is_reachable(base_url: &str) -> bool {
+    is_reachable_within(base_url, PROBE_TIMEOUT)
+}
+
+fn is_reachable_within(base_url: &str, timeout: Duration) -> bool {
+    let (tx, rx) = channel();
+    ehttp::fetch(ehttp::Request::get(base_url), move |response| {
+        let _ = tx.send(response.is_ok());
+    });
+    rx.recv_timeout(timeout).unwrap_or(false)
+}
+
+fn decode<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, FetchError> {
+    Ok(serde_json::from_slice(bytes)?)
+}
+
+/// Asks the TMDB API, or anything that speaks it at `base_url`.
+pub struct TmdbProvider {
+    pub base_url: String,
+    pub settings: Settings,
+    /// If set, responses are also written here for [`FixtureProvider`].
+    pub record_dir: Option<PathBuf>,
+    /// If set, responses younger than `max_age` are answered from here, and
+    /// older ones too when TMDB can't be reached.
+    pub cache: Option<DiskCache>,
+    pub max_age: Duration,
+    /// Only answer from `cache`, whatever the age of the response.
+    pub offline: bool,
+    pub client: Arc<HttpClient>,
+}
+
+impl TmdbProvider {
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            base_url: settings.api_base_url.clone(),
+            settings,
+            record_dir: None,
+            cache: None,
+            max_age: API_TTL,
+            offline: false,
+            client: HttpClient::shared(),
+        }
+    }
+
+    /// Sends the requests through `client` rather than the shared one.
+    pub fn with_client(mut self, client: Arc<HttpClient>) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Where the response to `endpoint` with `query` is cached. The query is
+    /// hashed, long searches would make too long a file name. The credential
+    /// is left out, it doesn't change the response.
+    fn cache_key(&self, endpoint: &Endpoint, query: &[(&str, &str)]) -> PathBuf {
+        PathBuf::from(encode(&self.base_url)).join(fixture_path(
+            &endpoint.path,
+            Some(&sha256(encode_query(query).as_bytes())),
+        ))
+    }
+
+    /// The parameters of `endpoint` with the language and region from the
+    /// settings.
+    fn query<'a>(&'a self, endpoint: &'a Endpoint) -> Vec<(&'a str, &'a str)> {
+        let mut query: Vec<(&str, &str)> = endpoint
+            .params
+            .iter()
+            .map(|(k, v)| (*k, v.as_str()))
+            .collect();
+        query.push(("language", &self.settings.language));
+        if !self.settings.region.is_empty() {
+            query.push(("region", &self.settings.region));
+        }
+        query
+    }
+
+    /// The response body for `endpoint`, from the cache if it has a fresh one.
+    fn get(&self, endpoint: &Endpoint) -> Result<Vec<u8>, FetchError> {
+        let query = self.query(endpoint);
+        let cache_key = self.cache_key(endpoint, &query);
+        if self.offline {
+            return self
+                .cache
+                .as_ref()
+                .and_then(|cache| cache.response(&cache_key, None))
+                .ok_or(FetchError::Offline);
+        }
+        if let Some(bytes) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.response(&cache_key, Some(self.max_age)))
+        {
+            return Ok(bytes);
+        }
+        let bytes = match self.request(endpoint, query) {
+            Err(FetchError::Network(err)) => {
+                // Stale beats nothing when offline.
+                return self
+                    .cache
+                    .as_ref()
+                    .and_then(|cache| cache.response(&cache_key, None))
+                    .ok_or(FetchError::Network(err));
+            }
+            result => result?,
+        };
+        if let Some(cache) = &self.cache {
+            // Caching is best effort, like recording.
+            let _ = cache.store_response(&cache_key, &bytes);
+        }
+        Ok(bytes)
+    }
+
+    /// Requests `endpoint` with `query` and the credential from the settings.
+    fn request<'a>(
+        &'a self,
+        endpoint: &Endpoint,
+        mut query: Vec<(&'a str, &'a str)>,
+    ) -> Result<Vec<u8>, FetchError> {
+        let mut headers = vec![("Accept", "application/json".to_owned())];
+        match self.settings.auth().ok_or(FetchError::NoCredential)? {
+            Auth::ApiKey(key) => query.push(("api_key", key)),
+            Auth::Token(token) => headers.push(("Authorization", format!("Bearer {token}"))),
+        }
+        let query = encode_query(&query);
+        let req = ehttp::Request {
+            url: format!(
+                "{}{}?{query}",
+                self.base_url.trim_end_matches('/'),
+                endpoint.path
+            ),
+            method: "GET".to_owned(),
+            body: Vec::new(),
+            headers: headers
+                .into_iter()
+                .map(|(k, v)| (k.to_owned(), v))
+                .collect(),
+        };
+        let bytes = self.client.fetch(&req)?;
+
+        if let Some(dir) = &self.record_dir {
+            let file = dir.join(endpoint.fixture_path());
+            // Recording is best effort, the response is fine either way.
+            if let Some(parent) = file.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            let _ = fs::write(file, &bytes);
+        }
+        Ok(bytes)
+    }
+}
+
+impl MetadataProvider for TmdbProvider {
+    fn name(&self) -> &'static str {
+        "TMDB"
+    }
+
+    fn find_by_imdb(&self, id: TitleId) -> Result<tmdb::FindById, FetchError> {
+        decode(&self.get(&Endpoint::find(id))?)
+    }
+
+    fn details(&self, id: i32, typ: TitleType) -> Result<tmdb::DetailWCredits, FetchError> {
+        decode(&self.get(&Endpoint::details(id, typ))?)
+    }
+
+    fn related(
+        &self,
+        id: i32,
+        typ: TitleType,
+        related: Related,
+    ) -> Result<tmdb::Page<tmdb::RelatedTitle>, FetchError> {
+        decode(&self.get(&Endpoint::related(id, typ, related))?)
+    }
+
+    fn search(&self, query: &str) -> Result<tmdb::Page<tmdb::SearchResult>, FetchError> {
+        decode(&self.get(&Endpoint::search(query))?)
+    }
+}
+
+/// Replays responses recorded in a directory, see [`fixture_path`] for the
+/// layout. Missing recordings are answered like TMDB answers unknown ids.
+pub struct FixtureProvider {
+    dir: PathBuf,
+}
+
+impl FixtureProvider {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn get(&self, endpoint: &Endpoint) -> Result<Vec<u8>, FetchError> {
+        fs::read(self.dir.join(endpoint.fixture_path())).map_err(|_| FetchError::Status {
+            status: 404,
+            status_text: "Not Found".to_owned(),
+        })
+    }
+}
+
+impl MetadataProvider for FixtureProvider {
+    fn name(&self) -> &'static str {
+        "fixtures"
+    }
+
+    fn find_by_imdb(&self, id: TitleId) -> Result<tmdb::FindById, FetchError> {
+        decode(&self.get(&Endpoint::find(id))?)
+    }
+
+    fn details(&self, id: i32, typ: TitleType) -> Result<tmdb::DetailWCredits, FetchError> {
+        decode(&self.get(&Endpoint::details(id, typ))?)
+    }
+
+    fn related(
+        &self,
+        id: i32,
+        typ: TitleType,
+        related: Related,
+    ) -> Result<tmdb::Page<tmdb::RelatedTitle>, FetchError> {
+        decode(&self.get(&Endpoint::related(id, typ, related))?)
+    }
+
+    fn search(&self, query: &str) -> Result<tmdb::Page<tmdb::SearchResult>, FetchError> {
+        decode(&self.get(&Endpoint::search(query))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpConfig;
+    use crate::stub::{StubServer, TEST_FIXTURES};
+
+    fn tmdb_at(url: String, cache: &DiskCache) -> TmdbProvider {
+        let settings = Settings {
+            credential: "0123456789abcdef".to_owned(),
+            ..Default::default()
+        };
+        let client = HttpClient::new(HttpConfig {
+            max_retries: 0,
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        });
+        TmdbProvider {
+            cache: Some(cache.clone()),
+            ..TmdbProvider::new(settings)
+                .with_base_url(url)
+                .with_client(Arc::new(client))
+        }
+    }
+
+    fn temp_cache(name: &str) -> DiskCache {
+        let dir = std::env::temp_dir().join(format!("meepls-{name}-{}", std::process::id()));
+        DiskCache::new(dir)
+    }
+
+    #[test]
+    fn answers_from_the_cache() {
+        let server = StubServer::start(TEST_FIXTURES).unwrap();
+        let cache = temp_cache("provider-cache");
+        let mut provider = tmdb_at(server.url(), &cache);
+        assert_eq!(provider.search("Mr. Nobody").unwrap().results.len(), 3);
+        assert_eq!(provider.search("Mr. Nobody").unwrap().results.len(), 3);
+        assert_eq!(server.requests(), 1);
+
+        provider.offline = true;
+        assert_eq!(provider.search("Mr. Nobody").unwrap().results.len(), 3);
+        assert!(matches!(
+            provider.search("Mr. Smith Goes to Washington"),
+            Err(FetchError::Offline)
+        ));
+        cache.clear().unwrap();
+    }
+
+    #[test]
+    fn probes_give_up_on_silent_servers() {
+        let server = StubServer::start(TEST_FIXTURES).unwrap();
+        assert!(is_reachable(&server.url()));
+        // Accepts the connection but never answers.
+        let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", silent.local_addr().unwrap());
+        let started = std::time::Instant::now();
+        assert!(!is_reachable_within(&url, Duration::from_millis(200)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn caches_long_searches() {
+        let cache = temp_cache("provider-long");
+        let mut provider = tmdb_at("http://127.0.0.1:9".to_owned(), &cache);
+        provider.offline = true;
+        let query = "Night of the Day of the Dawn of the Son of the Bride of the Return of the \
+                     Revenge of the Terror of the Attack of the Evil, Mutant, Hellbound, \
+                     Flesh-Eating, Crawling, Alien, Zombified, Subhumanoid Living Dead — Part 5";
+        let endpoint = Endpoint::search(query);
+        let key = provider.cache_key(&endpoint, &provider.query(&endpoint));
+        assert!(key.iter().all(|part| part.len() <= 255), "{key:?}");
+
+        cache
+            .store_response(&key, br#"{"page": 1, "results": []}"#)
+            .unwrap();
+        assert!(provider.search(query).unwrap().results.is_empty());
+        cache.clear().unwrap();
+    }
+
+    #[test]
+    fn dotted_queries_get_their_own_fixture() {
+        let nobody = fixture_path("/search/multi", Some(&encode("Mr. Nobody")));
+        let smith = fixture_path(
+            "/search/multi",
+            Some(&encode("Mr. Smith Goes to Washington")),
+        );
+        assert_ne!(nobody, smith);
+        assert!(nobody.to_string_lossy().ends_with("Nobody.json"));
+        assert_eq!(
+            fixture_path("/movie/949", None),
+            PathBuf::from("movie/949.json")
+        );
+    }
+}
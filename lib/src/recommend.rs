@@ -1,11 +1,729 @@
-#[derive(Default, Debug, serde::Deserialize, serde::Serialize, Clone, Copy)]
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        mpsc::{channel, Receiver, TryRecvError},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use egui::RichText;
+use serde::{Deserialize, Serialize};
+
+use crate::fetch::{get_related_titles, get_title_from_tmdb, get_title_id_from_imdb};
+use crate::models::{TitleId, TitleType, WatchStatus};
+use crate::my_titles::{show_title_card, Title, Titles};
+use crate::provider::{self, MetadataProvider, Related};
+use crate::settings::Settings;
+
+/// Rating that counts as neutral; titles rated above pull the profile towards
+/// their features, titles rated below push it away.
+const NEUTRAL_RATING: f32 = 5.5;
+/// Weight of a title that is in the library but hasn't been rated.
+const UNRATED_WEIGHT: f32 = 0.2;
+
+const GENRE_WEIGHT: f32 = 0.45;
+const DIRECTOR_WEIGHT: f32 = 0.25;
+const ACTOR_WEIGHT: f32 = 0.2;
+const YEAR_WEIGHT: f32 = 0.1;
+
+fn rating_weight(my_rating: Option<f32>) -> f32 {
+    match my_rating {
+        Some(rating) => (rating - NEUTRAL_RATING) / (10.0 - NEUTRAL_RATING),
+        None => UNRATED_WEIGHT,
+    }
+}
+
+/// Affinities of a single feature kind (genres, directors, ...), normalized to
+/// `-1.0..=1.0`.
+#[derive(Default, Debug, Clone)]
+struct Affinities(HashMap<String, f32>);
+
+impl Affinities {
+    fn add(&mut self, features: &[String], weight: f32) {
+        for feature in features {
+            *self.0.entry(feature.clone()).or_default() += weight;
+        }
+    }
+
+    fn normalize(&mut self) {
+        let max = self.0.values().fold(0.0f32, |max, v| max.max(v.abs()));
+        if max > 0.0 {
+            self.0.values_mut().for_each(|v| *v /= max);
+        }
+    }
+
+    fn get(&self, feature: &str) -> f32 {
+        self.0.get(feature).copied().unwrap_or_default()
+    }
+
+    /// One factor per known feature, together worth `weight` times their mean
+    /// affinity.
+    fn mean_factors(
+        &self,
+        features: &[String],
+        weight: f32,
+        kind: impl Fn(String) -> FactorKind,
+    ) -> Vec<Factor> {
+        let share = weight / features.len().max(1) as f32;
+        features
+            .iter()
+            .map(|f| (f, self.get(f)))
+            .filter(|(_, affinity)| *affinity != 0.0)
+            .map(|(f, affinity)| Factor {
+                kind: kind(f.clone()),
+                weight: share * affinity,
+            })
+            .collect()
+    }
+
+    /// A factor for the feature with the strongest affinity, by absolute value.
+    fn strongest_factor(
+        &self,
+        features: &[String],
+        weight: f32,
+        kind: impl Fn(String) -> FactorKind,
+    ) -> Option<Factor> {
+        features
+            .iter()
+            .map(|f| (f, self.get(f)))
+            .filter(|(_, affinity)| *affinity != 0.0)
+            .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+            .map(|(f, affinity)| Factor {
+                kind: kind(f.clone()),
+                weight: weight * affinity,
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FactorKind {
+    /// The candidate is related to this library title on TMDB.
+    Source {
+        title: String,
+        my_rating: Option<f32>,
+    },
+    Director(String),
+    Actor(String),
+    Genre(String),
+    Year(u16),
+}
+
+impl std::fmt::Display for FactorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FactorKind::Source {
+                title,
+                my_rating: Some(rating),
+            } => write!(f, "Because you rated {title} {rating}★"),
+            FactorKind::Source {
+                title,
+                my_rating: None,
+            } => write!(f, "Because you added {title}"),
+            FactorKind::Director(name) => write!(f, "shares director {name}"),
+            FactorKind::Actor(name) => write!(f, "stars {name}"),
+            FactorKind::Genre(name) => write!(f, "{name}"),
+            FactorKind::Year(year) => write!(f, "from {year}"),
+        }
+    }
+}
+
+/// Something that contributed to a candidate's score.
+#[derive(Debug, Clone)]
+pub struct Factor {
+    pub kind: FactorKind,
+    /// The part of the score this factor is responsible for, negative if it
+    /// counted against the candidate.
+    pub weight: f32,
+}
+
+/// A one line explanation, e.g.
+/// "Because you rated Heat 9★ · shares director Michael Mann · Crime/Thriller".
+fn summarize(factors: &[Factor]) -> String {
+    let best = |pred: fn(&FactorKind) -> bool| {
+        factors
+            .iter()
+            .filter(|f| f.weight > 0.0 && pred(&f.kind))
+            .max_by(|a, b| a.weight.total_cmp(&b.weight))
+            .map(|f| f.kind.to_string())
+    };
+    let genres: Vec<String> = factors
+        .iter()
+        .filter(|f| f.weight > 0.0 && matches!(f.kind, FactorKind::Genre(_)))
+        .map(|f| f.kind.to_string())
+        .collect();
+    let parts = [
+        best(|k| matches!(k, FactorKind::Source { .. })),
+        best(|k| matches!(k, FactorKind::Director(_))),
+        best(|k| matches!(k, FactorKind::Actor(_))),
+        (!genres.is_empty()).then(|| genres.join("/")),
+    ];
+    parts.into_iter().flatten().collect::<Vec<_>>().join(" · ")
+}
+
+/// What the user likes, derived from the titles in their library.
+#[derive(Default, Debug, Clone)]
+pub struct TasteProfile {
+    genres: Affinities,
+    directors: Affinities,
+    actors: Affinities,
+    year_mean: f32,
+    year_spread: f32,
+}
+
+impl TasteProfile {
+    pub fn from_titles<'a>(titles: impl IntoIterator<Item = &'a Title>) -> Self {
+        let mut profile = Self::default();
+        let mut years = Vec::new();
+        for title in titles {
+            let weight = rating_weight(title.my_rating);
+            profile.genres.add(&title.genres, weight);
+            profile.directors.add(&title.directors, weight);
+            profile.actors.add(&title.actors, weight);
+            if weight > 0.0 {
+                years.push((title.year as f32, weight));
+            }
+        }
+        profile.genres.normalize();
+        profile.directors.normalize();
+        profile.actors.normalize();
+
+        let total: f32 = years.iter().map(|(_, w)| w).sum();
+        if total > 0.0 {
+            let mean = years.iter().map(|(y, w)| y * w).sum::<f32>() / total;
+            let variance = years
+                .iter()
+                .map(|(y, w)| w * (y - mean).powi(2))
+                .sum::<f32>()
+                / total;
+            profile.year_mean = mean;
+            // Don't let a library of same-year titles make every other year worthless.
+            profile.year_spread = variance.sqrt().max(5.0);
+        }
+        profile
+    }
+
+    /// How close `year` is to the years the user enjoys, in `0.0..=1.0`.
+    fn year_affinity(&self, year: u16) -> f32 {
+        if self.year_spread == 0.0 {
+            return 0.0;
+        }
+        let z = (year as f32 - self.year_mean) / self.year_spread;
+        (-0.5 * z * z).exp()
+    }
+
+    /// What makes `title` similar to this profile. The weights add up to a
+    /// score roughly in `-1.0..=1.0`.
+    pub fn factors(&self, title: &Title) -> Vec<Factor> {
+        let mut factors = self
+            .genres
+            .mean_factors(&title.genres, GENRE_WEIGHT, FactorKind::Genre);
+        factors.extend(self.directors.strongest_factor(
+            &title.directors,
+            DIRECTOR_WEIGHT,
+            FactorKind::Director,
+        ));
+        factors.extend(
+            self.actors
+                .mean_factors(&title.actors, ACTOR_WEIGHT, FactorKind::Actor),
+        );
+        let year = self.year_affinity(title.year);
+        if year > 0.0 {
+            factors.push(Factor {
+                kind: FactorKind::Year(title.year),
+                weight: YEAR_WEIGHT * year,
+            });
+        }
+        factors
+    }
+}
+
+/// Titles whose related titles are used as candidates, at most this many.
+const SEED_LIMIT: usize = 20;
+/// Size of the candidate pool, every candidate costs one details request.
+const CANDIDATE_LIMIT: usize = 40;
+/// Candidate details requested at the same time.
+const DETAILS_BATCH: usize = 8;
+/// How much the candidate pool score counts next to the taste profile.
+const POOL_WEIGHT: f32 = 0.3;
+
+fn related_weight(related: Related) -> f32 {
+    match related {
+        Related::Recommendations => 1.0,
+        Related::Similar => 0.6,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Candidate {
+    pub title: Title,
+    /// How strongly the candidate is related to the library, see [`CandidatePool`].
+    pub pool_score: f32,
+    /// Library titles this candidate was found through.
+    pub sources: Vec<TitleId>,
+}
+
+#[derive(Default)]
+struct PoolEntry {
+    score: f32,
+    sources: Vec<TitleId>,
+}
+
+/// Related titles of the library, merged and de-duplicated. Every time a title
+/// shows up for a seed it gains score by the seed's rating, its position in the
+/// list and the kind of list it came from.
+#[derive(Default)]
+struct CandidatePool {
+    entries: HashMap<(TitleType, i32), PoolEntry>,
+}
+
+impl CandidatePool {
+    fn add(&mut self, key: (TitleType, i32), source: TitleId, weight: f32) {
+        let entry = self.entries.entry(key).or_default();
+        entry.score += weight;
+        if !entry.sources.contains(&source) {
+            entry.sources.push(source);
+        }
+    }
+
+    fn into_best(self, limit: usize) -> Vec<((TitleType, i32), PoolEntry)> {
+        let mut entries: Vec<_> = self.entries.into_iter().collect();
+        entries.sort_by(|(_, a), (_, b)| b.score.total_cmp(&a.score));
+        entries.truncate(limit);
+        entries
+    }
+}
+
+/// The titles that tell about the user's taste, the ones on the watchlist
+/// haven't been seen yet.
+fn seen(titles: &Titles) -> impl Iterator<Item = &Title> {
+    titles
+        .values()
+        .filter(|t| t.status != WatchStatus::Watchlist)
+}
+
+/// Looks up titles related to the best rated ones in `titles` and returns the
+/// most relevant ones that aren't in the library yet, watchlist included.
+fn build_candidates(provider: &dyn MetadataProvider, titles: &Titles) -> Vec<Candidate> {
+    let mut seeds: Vec<&Title> = seen(titles)
+        .filter(|t| rating_weight(t.my_rating) > 0.0)
+        .collect();
+    seeds.sort_by(|a, b| rating_weight(b.my_rating).total_cmp(&rating_weight(a.my_rating)));
+    seeds.truncate(SEED_LIMIT);
+
+    let owned: HashSet<(TitleType, i32)> = titles
+        .values()
+        .filter_map(|t| Some((t.ty, t.tmdb_id?)))
+        .collect();
+
+    let mut pool = CandidatePool::default();
+    for seed in seeds {
+        let (tmdb_id, ty) = match seed.tmdb_id {
+            Some(tmdb_id) => (tmdb_id, seed.ty),
+            None => ok_or!(get_title_id_from_imdb(provider, seed.id), continue),
+        };
+        let seed_weight = rating_weight(seed.my_rating);
+        for related in [Related::Recommendations, Related::Similar] {
+            let results = ok_or!(get_related_titles(provider, tmdb_id, ty, related), continue);
+            for (rank, result) in results.into_iter().enumerate() {
+                let key = (ty, result.id);
+                if owned.contains(&key) {
+                    continue;
+                }
+                let weight = seed_weight * related_weight(related) / (rank as f32 + 1.0).sqrt();
+                pool.add(key, seed.id, weight);
+            }
+        }
+    }
+
+    let best = pool.into_best(CANDIDATE_LIMIT);
+    let mut candidates = Vec::with_capacity(best.len());
+    for batch in best.chunks(DETAILS_BATCH) {
+        let titles: Vec<Option<Title>> = thread::scope(|s| {
+            let handles: Vec<_> = batch
+                .iter()
+                .map(|((ty, id), _)| {
+                    s.spawn(move || get_title_from_tmdb(provider, *id, *ty, None).ok())
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().ok().flatten())
+                .collect()
+        });
+        for ((_, entry), title) in batch.iter().zip(titles) {
+            if let Some(title) = title {
+                candidates.push(Candidate {
+                    title,
+                    pool_score: entry.score,
+                    sources: entry.sources.clone(),
+                });
+            }
+        }
+    }
+    candidates
+}
+
+#[derive(Debug, Clone)]
+pub struct Recommendation {
+    /// Index into `RecommendApp::candidates`.
+    candidate: usize,
+    score: f32,
+    factors: Vec<Factor>,
+    summary: String,
+}
+
+impl Recommendation {
+    /// Roughly in `-1.0..=1.0`, the sum of the factors.
+    pub fn score(&self) -> f32 {
+        self.score
+    }
+
+    pub fn factors(&self) -> &[Factor] {
+        &self.factors
+    }
+
+    /// The factors that weigh the most, in a sentence.
+    pub fn summary(&self) -> &str {
+        &self.summary
+    }
+}
+
+#[derive(Default, Debug, Deserialize, Serialize)]
 #[serde(default)]
-pub struct RecommendApp {}
+pub struct RecommendApp {
+    /// Titles that may be recommended, the ones already in the library are skipped.
+    pub candidates: Vec<Candidate>,
+    #[serde(skip)]
+    recommendations: Vec<Recommendation>,
+    /// Library revision the recommendations were computed for.
+    #[serde(skip)]
+    revision: Option<u64>,
+    /// Set by the UI, the candidates are refreshed on the next sync.
+    #[serde(skip)]
+    refresh_requested: bool,
+    /// Candidates being looked up on TMDB by a worker thread.
+    #[serde(skip)]
+    pending: Option<Receiver<Vec<Candidate>>>,
+    #[serde(skip)]
+    pub settings: Arc<Mutex<Settings>>,
+}
+
+impl RecommendApp {
+    /// Recomputes the recommendations if the library or the candidates changed
+    /// since last time. Starts looking for new candidates if that was asked for.
+    pub fn sync(&mut self, ctx: &egui::Context, titles: &Titles, revision: u64) {
+        if std::mem::take(&mut self.refresh_requested) && self.pending.is_none() {
+            self.find_candidates(ctx, titles);
+        }
+        self.poll_candidates();
+        if self.revision == Some(revision) {
+            return;
+        }
+        self.revision = Some(revision);
+        self.rank(titles);
+    }
+
+    /// A copy of what is saved, the candidates.
+    pub fn to_store(&self) -> Self {
+        Self {
+            candidates: self.candidates.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Looks up candidates on a worker thread, they replace the pool once
+    /// [`RecommendApp::sync`] finds them done.
+    fn find_candidates(&mut self, ctx: &egui::Context, titles: &Titles) {
+        let (tx, rx) = channel();
+        let settings = self.settings.lock().unwrap().effective();
+        let titles = titles.clone();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let provider = provider::from_settings(&settings);
+            let _ = tx.send(build_candidates(provider.as_ref(), &titles));
+            ctx.request_repaint();
+        });
+        self.pending = Some(rx);
+    }
+
+    fn poll_candidates(&mut self) {
+        let Some(rx) = &self.pending else {
+            return;
+        };
+        match rx.try_recv() {
+            Ok(candidates) => {
+                self.candidates = candidates;
+                // Ranked anew by the rest of the sync.
+                self.revision = None;
+            }
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {}
+        }
+        self.pending = None;
+    }
+
+    /// Replaces the candidate pool with titles related to the library, and
+    /// waits for it.
+    pub fn refresh_candidates(&mut self, titles: &Titles) {
+        let provider = provider::from_settings(&self.settings.lock().unwrap().effective());
+        self.candidates = build_candidates(provider.as_ref(), titles);
+        self.rank(titles);
+    }
+
+    /// The recommended titles, best first.
+    pub fn recommended(&self) -> impl Iterator<Item = (&Title, &Recommendation)> {
+        self.recommendations
+            .iter()
+            .map(|rec| (&self.candidates[rec.candidate].title, rec))
+    }
+
+    pub(crate) fn rank(&mut self, titles: &Titles) {
+        let profile = TasteProfile::from_titles(seen(titles));
+        let library: HashMap<TitleId, &Title> = titles.values().map(|t| (t.id, t)).collect();
+        let max_pool = self
+            .candidates
+            .iter()
+            .fold(0.0f32, |max, c| max.max(c.pool_score));
+        self.recommendations = self
+            .candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !library.contains_key(&c.title.id))
+            .map(|(i, c)| {
+                let pool = if max_pool > 0.0 {
+                    c.pool_score / max_pool
+                } else {
+                    0.0
+                };
+                // The pool score is split evenly among the titles it came from.
+                let sources: Vec<&Title> = c
+                    .sources
+                    .iter()
+                    .filter_map(|id| library.get(id).copied())
+                    .collect();
+                let mut factors: Vec<Factor> = sources
+                    .iter()
+                    .map(|source| Factor {
+                        kind: FactorKind::Source {
+                            title: source.title.clone(),
+                            my_rating: source.my_rating,
+                        },
+                        weight: POOL_WEIGHT * pool / sources.len() as f32,
+                    })
+                    .collect();
+                factors.extend(profile.factors(&c.title));
+                factors.sort_by(|a, b| b.weight.abs().total_cmp(&a.weight.abs()));
+                Recommendation {
+                    candidate: i,
+                    score: factors.iter().map(|f| f.weight).sum(),
+                    summary: summarize(&factors),
+                    factors,
+                }
+            })
+            .collect();
+        self.recommendations
+            .sort_by(|a, b| b.score.total_cmp(&a.score));
+    }
+}
 
 impl eframe::App for RecommendApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.label("Recommend hello");
+            ui.horizontal(|ui| {
+                ui.heading("Recommended for you");
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Max), |ui| {
+                    let offline = self.settings.lock().unwrap().is_offline();
+                    if self.pending.is_some() {
+                        ui.label("Looking for titles…");
+                        ui.spinner();
+                    } else if ui
+                        .add_enabled(!offline, egui::Button::new("Find new titles"))
+                        .on_disabled_hover_text("Needs TMDB, the current titles are kept offline")
+                        .clicked()
+                    {
+                        self.refresh_requested = true;
+                    }
+                });
+            });
+            ui.separator();
+            if self.recommendations.is_empty() {
+                ui.label("Nothing to recommend yet.");
+                return;
+            }
+            let settings = self.settings.lock().unwrap().effective();
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for rec in &self.recommendations {
+                    let title = &self.candidates[rec.candidate].title;
+                    let not_rated = |ui: &mut egui::Ui| {
+                        ui.weak("Not rated");
+                    };
+                    show_title_card(ui, &settings, title, not_rated, |ui| {
+                        ui.label(format!("{:.0}% match", (rec.score.max(0.0) * 100.0)));
+                    });
+                    ui.label(RichText::new(&rec.summary).italics());
+                    egui::CollapsingHeader::new("Why was this recommended?")
+                        .id_source(("why", rec.candidate))
+                        .show(ui, |ui| {
+                            egui::Grid::new(("factors", rec.candidate))
+                                .striped(true)
+                                .show(ui, |ui| {
+                                    for factor in &rec.factors {
+                                        ui.label(factor.kind.to_string());
+                                        ui.label(format!("{:+.3}", factor.weight));
+                                        ui.end_row();
+                                    }
+                                });
+                        });
+                    ui.separator();
+                }
+            });
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::FixtureProvider;
+    use crate::stub::TEST_FIXTURES;
+
+    fn title(
+        id: u32,
+        name: &str,
+        my_rating: Option<f32>,
+        genres: &[&str],
+        director: &str,
+    ) -> Title {
+        Title {
+            my_rating,
+            year: 1995,
+            genres: genres.iter().map(|g| g.to_string()).collect(),
+            directors: vec![director.to_owned()],
+            ..Title::test(id, name)
+        }
+    }
+
+    fn library(titles: impl IntoIterator<Item = Title>) -> Titles {
+        titles.into_iter().map(|t| (t.id, t)).collect()
+    }
+
+    #[test]
+    fn pools_related_titles_not_in_the_library() {
+        let leon = Title {
+            tmdb_id: Some(101),
+            ..title(110413, "Léon", Some(9.0), &["Crime"], "Luc Besson")
+        };
+        let heat = Title {
+            tmdb_id: Some(949),
+            status: WatchStatus::Watchlist,
+            ..title(113277, "Heat", None, &["Crime"], "Michael Mann")
+        };
+        let titles = library([leon, heat]);
+        let provider = FixtureProvider::new(TEST_FIXTURES);
+        let candidates = build_candidates(&provider, &titles);
+        // Heat is on the watchlist, Reservoir Dogs has no details recorded.
+        let found: Vec<(&str, &[TitleId])> = candidates
+            .iter()
+            .map(|c| (c.title.title.as_str(), &c.sources[..]))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("Kill Bill: Vol. 1", &[TitleId(110413)][..]),
+                ("Pulp Fiction", &[TitleId(110413)][..]),
+            ]
+        );
+        // Found in both lists, so ahead of Pulp Fiction despite its rank.
+        assert!(candidates[0].pool_score > candidates[1].pool_score);
+    }
+
+    #[test]
+    fn summary_names_the_strongest_factors() {
+        let factor = |kind, weight| Factor { kind, weight };
+        let source = |title: &str, my_rating| FactorKind::Source {
+            title: title.to_owned(),
+            my_rating,
+        };
+        let factors = [
+            factor(FactorKind::Genre("Crime".to_owned()), 0.2),
+            factor(FactorKind::Actor("Al Pacino".to_owned()), 0.05),
+            factor(FactorKind::Director("Ridley Scott".to_owned()), 0.1),
+            factor(source("Collateral", None), 0.1),
+            factor(FactorKind::Genre("Romance".to_owned()), -0.3),
+            factor(FactorKind::Director("Michael Mann".to_owned()), 0.25),
+            factor(source("Heat", Some(9.0)), 0.3),
+            factor(FactorKind::Actor("Robert De Niro".to_owned()), 0.08),
+            factor(FactorKind::Genre("Thriller".to_owned()), 0.1),
+            factor(FactorKind::Year(1995), 0.09),
+        ];
+        assert_eq!(
+            summarize(&factors),
+            "Because you rated Heat 9★ · shares director Michael Mann · stars Robert De Niro · Crime/Thriller"
+        );
+        assert_eq!(
+            summarize(&[factor(source("Heat", None), 0.1)]),
+            "Because you added Heat"
+        );
+        assert_eq!(summarize(&[factor(FactorKind::Year(1995), 0.1)]), "");
+    }
+
+    fn score(profile: &TasteProfile, title: &Title) -> f32 {
+        profile.factors(title).iter().map(|f| f.weight).sum()
+    }
+
+    #[test]
+    fn matches_score_above_the_rest() {
+        let titles = library([
+            title(1, "Heat", Some(9.0), &["Crime", "Thriller"], "Michael Mann"),
+            title(2, "Collateral", Some(8.0), &["Crime"], "Michael Mann"),
+            title(3, "Notting Hill", Some(2.0), &["Romance"], "Roger Michell"),
+        ]);
+        let profile = TasteProfile::from_titles(seen(&titles));
+        let same_director = title(10, "Thief", None, &["Drama"], "Michael Mann");
+        let same_genre = title(11, "Ronin", None, &["Crime"], "John Frankenheimer");
+        let neither = title(12, "Up", None, &["Animation"], "Pete Docter");
+        let disliked = title(13, "Love Actually", None, &["Romance"], "Richard Curtis");
+        assert!(score(&profile, &same_director) > score(&profile, &neither));
+        assert!(score(&profile, &same_genre) > score(&profile, &neither));
+        assert!(score(&profile, &disliked) < score(&profile, &neither));
+    }
+
+    #[test]
+    fn watchlist_is_left_out_of_the_profile() {
+        let mut unseen = title(2, "Hereditary", None, &["Horror"], "Ari Aster");
+        unseen.status = WatchStatus::Watchlist;
+        let titles = library([
+            title(1, "Heat", Some(9.0), &["Crime"], "Michael Mann"),
+            unseen,
+        ]);
+        let seen_ids: Vec<TitleId> = seen(&titles).map(|t| t.id).collect();
+        assert_eq!(seen_ids, [TitleId(1)]);
+        let profile = TasteProfile::from_titles(seen(&titles));
+        assert_eq!(profile.genres.get("Horror"), 0.0);
+        assert_eq!(profile.directors.get("Ari Aster"), 0.0);
+        assert!(profile.genres.get("Crime") > 0.0);
+    }
+
+    #[test]
+    fn ranks_candidates_by_the_profile() {
+        let titles = library([title(1, "Heat", Some(9.0), &["Crime"], "Michael Mann")]);
+        let candidate = |title| Candidate {
+            title,
+            pool_score: 0.0,
+            sources: Vec::new(),
+        };
+        let mut app = RecommendApp {
+            candidates: vec![
+                candidate(title(10, "Up", None, &["Animation"], "Pete Docter")),
+                candidate(title(11, "Thief", None, &["Crime"], "Michael Mann")),
+                // Already in the library, never recommended.
+                candidate(title(1, "Heat", None, &["Crime"], "Michael Mann")),
+            ],
+            ..Default::default()
+        };
+        app.rank(&titles);
+        let ranked: Vec<&str> = app.recommended().map(|(t, _)| t.title.as_str()).collect();
+        assert_eq!(ranked, ["Thief", "Up"]);
+    }
+}
@@ -0,0 +1,331 @@
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cache::DiskCache;
+
+const CREDENTIAL_ENV: &str = "MEEPLS_TMDB_CREDENTIAL";
+const LANGUAGE_ENV: &str = "MEEPLS_TMDB_LANGUAGE";
+const REGION_ENV: &str = "MEEPLS_TMDB_REGION";
+const IMAGE_SIZE_ENV: &str = "MEEPLS_TMDB_IMAGE_SIZE";
+const API_URL_ENV: &str = "MEEPLS_TMDB_API_URL";
+
+const TMDB_API_BASE: &str = "https://api.themoviedb.org/3";
+
+pub(crate) const TMDB_IMG_BASE: &str = "https://image.tmdb.org/t/p/";
+
+/// Poster widths TMDB serves.
+pub const IMAGE_SIZES: [&str; 7] = ["w92", "w154", "w185", "w342", "w500", "w780", "original"];
+
+/// How requests to TMDB are authenticated.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Auth<'a> {
+    /// A v3 API key, sent as the `api_key` query parameter.
+    ApiKey(&'a str),
+    /// A v4 read access token, sent as a bearer token.
+    Token(&'a str),
+}
+
+/// Shown in place of the credential, which shouldn't end up in logs.
+const REDACTED: &str = "<redacted>";
+
+impl std::fmt::Debug for Auth<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Auth::ApiKey(_) => f.debug_tuple("ApiKey").field(&REDACTED).finish(),
+            Auth::Token(_) => f.debug_tuple("Token").field(&REDACTED).finish(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    /// TMDB v3 API key or v4 read access token, nothing can be fetched without.
+    pub credential: String,
+    pub language: String,
+    /// ISO 3166-1 country code used to localize release dates, may be empty.
+    pub region: String,
+    /// One of [`IMAGE_SIZES`].
+    pub image_size: String,
+    /// Where the TMDB API is, only worth changing to point at a stub server.
+    pub api_base_url: String,
+    /// Metadata older than this is fetched again in the background, never if 0.
+    pub refresh_after_days: u32,
+    /// Never reach out to TMDB, make do with the library and the cache.
+    pub work_offline: bool,
+    /// Set when TMDB couldn't be reached, cleared once it can be again.
+    #[serde(skip)]
+    pub unreachable: bool,
+}
+
+impl std::fmt::Debug for Settings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let credential = if self.credential.is_empty() {
+            ""
+        } else {
+            REDACTED
+        };
+        f.debug_struct("Settings")
+            .field("credential", &credential)
+            .field("language", &self.language)
+            .field("region", &self.region)
+            .field("image_size", &self.image_size)
+            .field("api_base_url", &self.api_base_url)
+            .field("refresh_after_days", &self.refresh_after_days)
+            .field("work_offline", &self.work_offline)
+            .field("unreachable", &self.unreachable)
+            .finish()
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            credential: String::new(),
+            language: "en-US".to_owned(),
+            region: String::new(),
+            image_size: "w500".to_owned(),
+            api_base_url: TMDB_API_BASE.to_owned(),
+            refresh_after_days: 30,
+            work_offline: false,
+            unreachable: false,
+        }
+    }
+}
+
+pub(crate) fn env_override(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+impl Settings {
+    /// These settings with the `MEEPLS_TMDB_*` environment variables applied on top.
+    pub fn effective(&self) -> Self {
+        Self {
+            credential: env_override(CREDENTIAL_ENV).unwrap_or_else(|| self.credential.clone()),
+            language: env_override(LANGUAGE_ENV).unwrap_or_else(|| self.language.clone()),
+            region: env_override(REGION_ENV).unwrap_or_else(|| self.region.clone()),
+            image_size: env_override(IMAGE_SIZE_ENV).unwrap_or_else(|| self.image_size.clone()),
+            api_base_url: env_override(API_URL_ENV).unwrap_or_else(|| self.api_base_url.clone()),
+            refresh_after_days: self.refresh_after_days,
+            work_offline: self.work_offline,
+            unreachable: self.unreachable,
+        }
+    }
+
+    /// Whether TMDB is out of reach, because the user said so or it is.
+    pub fn is_offline(&self) -> bool {
+        self.work_offline || self.unreachable
+    }
+
+    /// `None` until a credential is set, here or in `MEEPLS_TMDB_CREDENTIAL`.
+    pub fn auth(&self) -> Option<Auth<'_>> {
+        let credential = self.credential.trim();
+        if credential.is_empty() {
+            None
+        } else if credential.contains('.') {
+            // v4 tokens are JWTs, v3 keys are plain hex.
+            Some(Auth::Token(credential))
+        } else {
+            Some(Auth::ApiKey(credential))
+        }
+    }
+
+    pub fn poster_url(&self, poster_path: &str) -> String {
+        format!("{TMDB_IMG_BASE}{}{poster_path}", self.image_size)
+    }
+}
+
+pub struct SettingsApp {
+    pub settings: Arc<Mutex<Settings>>,
+    reveal_credential: bool,
+    cache: Option<DiskCache>,
+    /// Size of `cache` in bytes, measured when the view is first shown.
+    cache_size: Option<u64>,
+    /// Why the cache couldn't be cleared.
+    cache_error: Option<String>,
+}
+
+impl SettingsApp {
+    pub fn new(settings: Arc<Mutex<Settings>>) -> Self {
+        Self {
+            settings,
+            reveal_credential: false,
+            cache: DiskCache::in_data_dir(),
+            cache_size: None,
+            cache_error: None,
+        }
+    }
+
+    fn show_cache(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Cache");
+        let Some(cache) = &self.cache else {
+            ui.weak("There is no data directory to keep a cache in.");
+            return;
+        };
+        let size = *self.cache_size.get_or_insert_with(|| cache.size());
+        ui.horizontal(|ui| {
+            ui.label(format!("{} in", format_size(size)));
+            ui.monospace(cache.dir().display().to_string());
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Clear cache").clicked() {
+                self.cache_error = cache.clear().err().map(|err| err.to_string());
+                ui.ctx().forget_all_images();
+                self.cache_size = None;
+            }
+            if ui.button("⟳").on_hover_text("Measure again").clicked() {
+                self.cache_size = None;
+            }
+        });
+        if let Some(err) = &self.cache_error {
+            ui.colored_label(
+                ui.visuals().error_fg_color,
+                format!("Couldn't clear the cache: {err}"),
+            );
+        }
+    }
+}
+
+/// `bytes` in KiB, MiB or GiB, whichever reads best.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["bytes", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} {}", UNITS[0])
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+fn overridden_hint(ui: &mut egui::Ui, env: &str) {
+    if env_override(env).is_some() {
+        ui.label(
+            egui::RichText::new(format!("overridden by ${env}")).color(ui.visuals().warn_fg_color),
+        );
+    }
+}
+
+impl eframe::App for SettingsApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Settings");
+            ui.separator();
+            let mut settings = self.settings.lock().unwrap();
+            egui::Grid::new("tmdb_settings")
+                .num_columns(3)
+                .spacing([12.0, 8.0])
+                .show(ui, |ui| {
+                    ui.label("TMDB API key or token");
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::TextEdit::singleline(&mut settings.credential)
+                                .password(!self.reveal_credential)
+                                .hint_text("required"),
+                        );
+                        ui.toggle_value(&mut self.reveal_credential, "👁");
+                    });
+                    overridden_hint(ui, CREDENTIAL_ENV);
+                    ui.end_row();
+
+                    if settings.effective().auth().is_none() {
+                        ui.label("");
+                        ui.horizontal_wrapped(|ui| {
+                            ui.colored_label(
+                                ui.visuals().warn_fg_color,
+                                "Set a TMDB credential to fetch titles, get one at",
+                            );
+                            ui.hyperlink("https://www.themoviedb.org/settings/api");
+                        });
+                        ui.end_row();
+                    }
+
+                    ui.label("Language");
+                    ui.add(egui::TextEdit::singleline(&mut settings.language).hint_text("en-US"));
+                    overridden_hint(ui, LANGUAGE_ENV);
+                    ui.end_row();
+
+                    ui.label("Region");
+                    ui.add(egui::TextEdit::singleline(&mut settings.region).hint_text("US"));
+                    overridden_hint(ui, REGION_ENV);
+                    ui.end_row();
+
+                    ui.label("Poster size");
+                    egui::ComboBox::from_id_source("image_size")
+                        .selected_text(settings.image_size.clone())
+                        .show_ui(ui, |ui| {
+                            for size in IMAGE_SIZES {
+                                ui.selectable_value(
+                                    &mut settings.image_size,
+                                    size.to_owned(),
+                                    size,
+                                );
+                            }
+                        });
+                    overridden_hint(ui, IMAGE_SIZE_ENV);
+                    ui.end_row();
+
+                    ui.label("API base URL");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut settings.api_base_url)
+                            .hint_text(TMDB_API_BASE),
+                    );
+                    overridden_hint(ui, API_URL_ENV);
+                    ui.end_row();
+
+                    ui.label("Refresh metadata after");
+                    ui.add(
+                        egui::DragValue::new(&mut settings.refresh_after_days)
+                            .clamp_range(0..=3650)
+                            .suffix(" days"),
+                    )
+                    .on_hover_text("0 to only refresh by hand");
+                    ui.end_row();
+
+                    ui.label("Work offline");
+                    ui.checkbox(&mut settings.work_offline, "").on_hover_text(
+                        "Only use the library and the cache, adds wait until unchecked",
+                    );
+                    if settings.unreachable && !settings.work_offline {
+                        ui.colored_label(ui.visuals().warn_fg_color, "TMDB can't be reached");
+                    }
+                    ui.end_row();
+                });
+            drop(settings);
+            ui.separator();
+            self.show_cache(ui);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_leaves_the_credential_out() {
+        let settings = Settings {
+            credential: "0123456789abcdef".to_owned(),
+            ..Default::default()
+        };
+        for debug in [
+            format!("{settings:?}"),
+            format!("{:?}", settings.auth()),
+            format!(
+                "{:?}",
+                crate::ToStore {
+                    settings,
+                    ..Default::default()
+                }
+            ),
+        ] {
+            assert!(!debug.contains("0123456789abcdef"), "{debug}");
+            assert!(debug.contains(REDACTED), "{debug}");
+        }
+    }
+}
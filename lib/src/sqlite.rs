@@ -0,0 +1,544 @@
+use std::{collections::HashMap, path::Path};
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::models::TitleId;
+use crate::my_titles::{RatingEdit, Title, TitleList, Titles};
+use crate::store::{load_state, save_state, StateStore, StoreError};
+use crate::ToStore;
+
+/// Bumped with a migration added to [`MIGRATIONS`] whenever the schema changes.
+const SCHEMA_VERSION: u32 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE titles (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    year INTEGER NOT NULL,
+    type TEXT NOT NULL,
+    rating REAL NOT NULL,
+    my_rating REAL,
+    poster_img TEXT NOT NULL,
+    description TEXT NOT NULL,
+    tmdb_id INTEGER,
+    watch_count INTEGER NOT NULL,
+    last_watched TEXT,
+    status TEXT NOT NULL,
+    added_at TEXT,
+    notes TEXT NOT NULL,
+    fetched_at TEXT,
+    fetched_from TEXT NOT NULL
+);
+CREATE TABLE people (
+    title_id TEXT NOT NULL REFERENCES titles (id) ON DELETE CASCADE,
+    role TEXT NOT NULL CHECK (role IN ('director', 'actor')),
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    PRIMARY KEY (title_id, role, position)
+);
+CREATE INDEX people_name ON people (name);
+CREATE TABLE genres (
+    title_id TEXT NOT NULL REFERENCES titles (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    PRIMARY KEY (title_id, position)
+);
+CREATE TABLE tags (
+    title_id TEXT NOT NULL REFERENCES titles (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    PRIMARY KEY (title_id, name)
+);
+CREATE TABLE rating_history (
+    title_id TEXT NOT NULL REFERENCES titles (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    at TEXT NOT NULL,
+    rating REAL,
+    PRIMARY KEY (title_id, position)
+);
+CREATE TABLE lists (
+    position INTEGER PRIMARY KEY,
+    name TEXT NOT NULL
+);
+CREATE TABLE list_titles (
+    list INTEGER NOT NULL REFERENCES lists (position) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    title_id TEXT NOT NULL,
+    PRIMARY KEY (list, position)
+);
+-- Set once the library was taken over from eframe's storage.
+CREATE TABLE meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+";
+
+/// `MIGRATIONS[i]` takes the schema from version `i + 1` to `i + 2`.
+const MIGRATIONS: [&str; (SCHEMA_VERSION - 1) as usize] = [];
+
+const IMPORTED_KEY: &str = "imported_from_eframe";
+
+/// Unit enums are stored by variant name, as serde names them.
+fn enum_to_sql<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => unreachable!("only used for unit enums"),
+    }
+}
+
+fn enum_from_sql<T: DeserializeOwned>(idx: usize, name: String) -> rusqlite::Result<T> {
+    serde_json::from_value(serde_json::Value::String(name)).map_err(|err| {
+        rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, err.into())
+    })
+}
+
+fn id_from_sql(idx: usize, id: String) -> rusqlite::Result<TitleId> {
+    id.parse().map_err(|_| {
+        rusqlite::Error::FromSqlConversionFailure(
+            idx,
+            rusqlite::types::Type::Text,
+            format!("{id} isn't an IMDb id").into(),
+        )
+    })
+}
+
+/// Keeps the titles and lists in a SQLite database, one row per title, person,
+/// genre and so on, so they can be queried outside the app and only what
+/// changed is written. The rest of the state stays in eframe's storage.
+pub struct SqliteStore {
+    conn: Connection,
+    /// The library as last loaded or saved, to tell what changed.
+    saved: Titles,
+    saved_lists: Vec<TitleList>,
+    /// Why the state outside the database couldn't be loaded.
+    warning: Option<StoreError>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self, StoreError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|err| StoreError::Io(err.to_string()))?;
+        }
+        Self::with_connection(Connection::open(path)?, &path.display().to_string())
+    }
+
+    /// Sets up the schema in `conn`, the database at `location`.
+    fn with_connection(mut conn: Connection, location: &str) -> Result<Self, StoreError> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(StoreError::TooNew(version, location.to_owned()));
+        }
+        let tx = conn.transaction()?;
+        if version == 0 {
+            tx.execute_batch(SCHEMA)?;
+        } else {
+            for migration in &MIGRATIONS[version as usize - 1..] {
+                tx.execute_batch(migration)?;
+            }
+        }
+        tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        tx.commit()?;
+        Ok(Self {
+            conn,
+            saved: Titles::new(),
+            saved_lists: Vec::new(),
+            warning: None,
+        })
+    }
+
+    fn read_titles(&self) -> rusqlite::Result<Titles> {
+        let mut titles = Titles::new();
+        let mut stmt = self.conn.prepare(
+            "SELECT id, title, year, type, rating, my_rating, poster_img, description, tmdb_id,
+                    watch_count, last_watched, status, added_at, notes, fetched_at, fetched_from
+             FROM titles",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(Title {
+                id: id_from_sql(0, row.get(0)?)?,
+                title: row.get(1)?,
+                year: row.get(2)?,
+                ty: enum_from_sql(3, row.get(3)?)?,
+                rating: row.get(4)?,
+                my_rating: row.get(5)?,
+                directors: Vec::new(),
+                actors: Vec::new(),
+                genres: Vec::new(),
+                poster_img: row.get(6)?,
+                description: row.get(7)?,
+                tmdb_id: row.get(8)?,
+                watch_count: row.get(9)?,
+                last_watched: row.get(10)?,
+                status: enum_from_sql(11, row.get(11)?)?,
+                rating_history: Vec::new(),
+                added_at: row.get(12)?,
+                notes: row.get(13)?,
+                tags: Default::default(),
+                fetched_at: row.get(14)?,
+                fetched_from: row.get(15)?,
+            })
+        })?;
+        for title in rows {
+            let title = title?;
+            titles.insert(title.id, title);
+        }
+
+        // Ordered by position, so pushing keeps the order.
+        let mut stmt = self
+            .conn
+            .prepare("SELECT title_id, role, name FROM people ORDER BY title_id, role, position")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let Some(title) = titles.get_mut(&id_from_sql(0, row.get(0)?)?) else {
+                continue;
+            };
+            let role: String = row.get(1)?;
+            let people = match role.as_str() {
+                "director" => &mut title.directors,
+                _ => &mut title.actors,
+            };
+            people.push(row.get(2)?);
+        }
+        let mut stmt = self
+            .conn
+            .prepare("SELECT title_id, name FROM genres ORDER BY title_id, position")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            if let Some(title) = titles.get_mut(&id_from_sql(0, row.get(0)?)?) {
+                title.genres.push(row.get(1)?);
+            }
+        }
+        let mut stmt = self.conn.prepare("SELECT title_id, name FROM tags")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            if let Some(title) = titles.get_mut(&id_from_sql(0, row.get(0)?)?) {
+                title.tags.insert(row.get(1)?);
+            }
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT title_id, at, rating FROM rating_history ORDER BY title_id, position",
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            if let Some(title) = titles.get_mut(&id_from_sql(0, row.get(0)?)?) {
+                title.rating_history.push(RatingEdit {
+                    at: row.get(1)?,
+                    rating: row.get(2)?,
+                });
+            }
+        }
+        Ok(titles)
+    }
+
+    fn read_lists(&self) -> rusqlite::Result<Vec<TitleList>> {
+        let mut lists: Vec<TitleList> = Vec::new();
+        let mut positions = HashMap::new();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT position, name FROM lists ORDER BY position")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            positions.insert(row.get::<_, i64>(0)?, lists.len());
+            lists.push(TitleList {
+                name: row.get(1)?,
+                titles: Vec::new(),
+            });
+        }
+        let mut stmt = self
+            .conn
+            .prepare("SELECT list, title_id FROM list_titles ORDER BY list, position")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            if let Some(&i) = positions.get(&row.get::<_, i64>(0)?) {
+                lists[i].titles.push(id_from_sql(1, row.get(1)?)?);
+            }
+        }
+        Ok(lists)
+    }
+
+    /// Writes what changed since the last load or save.
+    fn write(&mut self, titles: &Titles, lists: &[TitleList]) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        write_changes(&tx, &self.saved, &self.saved_lists, titles, lists)?;
+        tx.commit()?;
+        self.saved = titles.clone();
+        self.saved_lists = lists.to_vec();
+        Ok(())
+    }
+
+    fn imported(&self) -> rusqlite::Result<bool> {
+        Ok(self
+            .conn
+            .query_row("SELECT 1 FROM meta WHERE key = ?1", [IMPORTED_KEY], |_| {
+                Ok(())
+            })
+            .optional()?
+            .is_some())
+    }
+}
+
+/// Writes what changed from `saved` and `saved_lists` to `titles` and `lists`.
+fn write_changes(
+    tx: &Transaction,
+    saved: &Titles,
+    saved_lists: &[TitleList],
+    titles: &Titles,
+    lists: &[TitleList],
+) -> rusqlite::Result<()> {
+    for id in saved.keys().filter(|id| !titles.contains_key(id)) {
+        tx.execute("DELETE FROM titles WHERE id = ?1", [id.to_string()])?;
+    }
+    for title in titles.values() {
+        if saved.get(&title.id) != Some(title) {
+            write_title(tx, title)?;
+        }
+    }
+    if saved_lists != lists {
+        tx.execute("DELETE FROM lists", [])?;
+        for (position, list) in lists.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO lists (position, name) VALUES (?1, ?2)",
+                params![position, list.name],
+            )?;
+            for (i, id) in list.titles.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO list_titles (list, position, title_id) VALUES (?1, ?2, ?3)",
+                    params![position, i, id.to_string()],
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the rows of `title`.
+fn write_title(tx: &Transaction, title: &Title) -> rusqlite::Result<()> {
+    let id = title.id.to_string();
+    // Cascades to the people, genres, tags and rating history.
+    tx.execute("DELETE FROM titles WHERE id = ?1", [&id])?;
+    tx.execute(
+        "INSERT INTO titles (id, title, year, type, rating, my_rating, poster_img, description,
+                             tmdb_id, watch_count, last_watched, status, added_at, notes,
+                             fetched_at, fetched_from)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+        params![
+            id,
+            title.title,
+            title.year,
+            enum_to_sql(&title.ty),
+            title.rating,
+            title.my_rating,
+            title.poster_img,
+            title.description,
+            title.tmdb_id,
+            title.watch_count,
+            title.last_watched,
+            enum_to_sql(&title.status),
+            title.added_at,
+            title.notes,
+            title.fetched_at,
+            title.fetched_from,
+        ],
+    )?;
+    let people = title
+        .directors
+        .iter()
+        .enumerate()
+        .map(|(i, name)| ("director", i, name))
+        .chain(
+            title
+                .actors
+                .iter()
+                .enumerate()
+                .map(|(i, name)| ("actor", i, name)),
+        );
+    for (role, position, name) in people {
+        tx.execute(
+            "INSERT INTO people (title_id, role, position, name) VALUES (?1, ?2, ?3, ?4)",
+            params![id, role, position, name],
+        )?;
+    }
+    for (position, name) in title.genres.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO genres (title_id, position, name) VALUES (?1, ?2, ?3)",
+            params![id, position, name],
+        )?;
+    }
+    for name in &title.tags {
+        tx.execute(
+            "INSERT INTO tags (title_id, name) VALUES (?1, ?2)",
+            params![id, name],
+        )?;
+    }
+    for (position, edit) in title.rating_history.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO rating_history (title_id, position, at, rating) VALUES (?1, ?2, ?3, ?4)",
+            params![id, position, edit.at, edit.rating],
+        )?;
+    }
+    Ok(())
+}
+
+impl StateStore for SqliteStore {
+    fn load(&mut self, storage: &dyn eframe::Storage) -> Result<Option<ToStore>, StoreError> {
+        if !self.imported()? {
+            // First run with the database, the library is still in eframe's storage.
+            // It's taken over in one go, or not at all and tried again next time.
+            let state = load_state(storage)?;
+            let tx = self.conn.transaction()?;
+            if let Some(state) = &state {
+                write_changes(
+                    &tx,
+                    &self.saved,
+                    &self.saved_lists,
+                    &state.titles,
+                    &state.lists,
+                )?;
+            }
+            tx.execute(
+                "INSERT INTO meta (key, value) VALUES (?1, ?2)",
+                params![IMPORTED_KEY, chrono::Utc::now().to_rfc3339()],
+            )?;
+            tx.commit()?;
+            if let Some(state) = &state {
+                self.saved = state.titles.clone();
+                self.saved_lists = state.lists.clone();
+            }
+            return Ok(state);
+        }
+        self.saved = self.read_titles()?;
+        self.saved_lists = self.read_lists()?;
+        // Losing the settings and such is no reason to start without the library.
+        let mut state = match load_state(storage) {
+            Ok(state) => state.unwrap_or_default(),
+            Err(err) => {
+                self.warning = Some(err);
+                ToStore::default()
+            }
+        };
+        state.titles = self.saved.clone();
+        state.lists = self.saved_lists.clone();
+        Ok(Some(state))
+    }
+
+    fn save(
+        &mut self,
+        storage: &mut dyn eframe::Storage,
+        mut state: ToStore,
+    ) -> Result<(), StoreError> {
+        let titles = std::mem::take(&mut state.titles);
+        let lists = std::mem::take(&mut state.lists);
+        self.write(&titles, &lists)?;
+        // eframe keeps the rest, without the library.
+        save_state(storage, &state);
+        Ok(())
+    }
+
+    fn take_warning(&mut self) -> Option<StoreError> {
+        self.warning.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::models::{TitleType, WatchStatus};
+    use crate::store::FileStorage;
+
+    fn store() -> SqliteStore {
+        SqliteStore::with_connection(Connection::open_in_memory().unwrap(), ":memory:").unwrap()
+    }
+
+    fn library() -> (Titles, Vec<TitleList>) {
+        let mut heat = Title::test(113277, "Heat");
+        heat.year = 1995;
+        heat.rating = 7.9;
+        heat.my_rating = Some(9.0);
+        heat.directors = vec!["Michael Mann".to_owned()];
+        heat.actors = vec!["Al Pacino".to_owned(), "Robert De Niro".to_owned()];
+        heat.genres = vec!["Crime".to_owned(), "Drama".to_owned(), "Action".to_owned()];
+        heat.tags = BTreeSet::from(["heist".to_owned(), "la".to_owned()]);
+        heat.rating_history = vec![
+            RatingEdit {
+                at: Utc.with_ymd_and_hms(2023, 5, 1, 20, 0, 0).unwrap(),
+                rating: Some(8.0),
+            },
+            RatingEdit {
+                at: Utc.with_ymd_and_hms(2023, 6, 1, 20, 0, 0).unwrap(),
+                rating: Some(9.0),
+            },
+        ];
+        heat.tmdb_id = Some(949);
+        heat.added_at = Some(Utc.with_ymd_and_hms(2023, 4, 30, 9, 15, 0).unwrap());
+        heat.notes = "The diner scene.".to_owned();
+        let mut bad = Title::test(903747, "Breaking Bad");
+        bad.ty = TitleType::Series;
+        bad.status = WatchStatus::Watching;
+        bad.watch_count = 2;
+        bad.last_watched = Some(Utc.with_ymd_and_hms(2023, 7, 1, 22, 0, 0).unwrap());
+        let lists = vec![
+            TitleList {
+                name: "Favourites".to_owned(),
+                titles: vec![bad.id, heat.id],
+            },
+            TitleList {
+                name: "Empty".to_owned(),
+                titles: Vec::new(),
+            },
+        ];
+        let titles = [heat, bad].into_iter().map(|t| (t.id, t)).collect();
+        (titles, lists)
+    }
+
+    #[test]
+    fn reads_back_what_it_wrote() {
+        let mut store = store();
+        let (mut titles, mut lists) = library();
+        store.write(&titles, &lists).unwrap();
+        assert_eq!(store.read_titles().unwrap(), titles);
+        assert_eq!(store.read_lists().unwrap(), lists);
+
+        // Only the changes are written the second time.
+        let heat = titles.get_mut(&TitleId(113277)).unwrap();
+        heat.genres.pop();
+        heat.tags.remove("la");
+        titles.remove(&TitleId(903747));
+        lists.remove(0);
+        store.write(&titles, &lists).unwrap();
+        assert_eq!(store.read_titles().unwrap(), titles);
+        assert_eq!(store.read_lists().unwrap(), lists);
+    }
+
+    #[test]
+    fn takes_the_library_over_from_eframe() {
+        // Never written, the storage only lives in memory.
+        let file = std::env::temp_dir().join(format!("meepls-sqlite-{}.ron", std::process::id()));
+        let mut storage = FileStorage::open(file).unwrap();
+        let (titles, lists) = library();
+        let state = ToStore {
+            titles: titles.clone(),
+            lists: lists.clone(),
+            ..Default::default()
+        };
+        save_state(&mut storage, &state);
+
+        let mut store = store();
+        let loaded = store.load(&storage).unwrap().unwrap();
+        assert_eq!(loaded.titles, titles);
+        assert!(store.imported().unwrap());
+        assert_eq!(store.read_titles().unwrap(), titles);
+        assert_eq!(store.read_lists().unwrap(), lists);
+
+        // From then on the database is what counts.
+        save_state(&mut storage, &ToStore::default());
+        let loaded = store.load(&storage).unwrap().unwrap();
+        assert_eq!(loaded.titles, titles);
+        assert_eq!(loaded.lists, lists);
+        assert!(store.take_warning().is_none());
+    }
+}
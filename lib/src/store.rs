@@ -0,0 +1,367 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::filter::TitleFilter;
+use crate::jobs::Job;
+use crate::my_titles::{Title, TitleList};
+use crate::{Anchor, RecommendApp, Settings, ToStore, APP_ID};
+
+/// Bumped whenever [`ToStore`] changes in a way older versions can't read,
+/// with a migration from the previous version added to [`MIGRATIONS`].
+pub const STORE_VERSION: u32 = 1;
+
+/// Storage key of the versioned state, kept as JSON so migrations can work on
+/// it without knowing the old types.
+const STATE_KEY: &str = "state";
+
+/// `MIGRATIONS[i]` takes the state from version `i` to `i + 1`. Version 0 is
+/// the unversioned state eframe stored under [`eframe::APP_KEY`] before.
+const MIGRATIONS: [fn(&mut Value); STORE_VERSION as usize] = [titles_by_id];
+
+/// Version 0 kept the titles by display title at first, by id later on.
+/// Version 1 keeps them by id.
+fn titles_by_id(state: &mut Value) {
+    if let Some(Value::Object(titles)) = state.get_mut("titles") {
+        *titles = std::mem::take(titles)
+            .into_iter()
+            .map(|(key, title)| {
+                let id = title
+                    .get("id")
+                    .and_then(Value::as_str)
+                    .map_or(key, str::to_owned);
+                (id, title)
+            })
+            .collect();
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Versioned<T> {
+    version: u32,
+    state: T,
+}
+
+/// The state as eframe stored it in RON, version 0. RON can't be read into a
+/// [`Value`] without the types, its enum variants are bare names, so it's read
+/// through these and handed to [`MIGRATIONS`] as JSON.
+#[derive(Serialize, Deserialize)]
+struct Unversioned {
+    selected_anchor: Anchor,
+    titles: BTreeMap<String, Title>,
+    #[serde(default)]
+    lists: Vec<TitleList>,
+    #[serde(default)]
+    filter: TitleFilter,
+    #[serde(default)]
+    queued: Vec<Job>,
+    recommend: RecommendApp,
+    #[serde(default)]
+    settings: Settings,
+}
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("the saved library can't be read ({reason}), it was backed up to {backup}")]
+    Unreadable { reason: String, backup: String },
+    #[error(
+        "the saved library is from a newer version of meepls (format {0}), it was backed up to {1}"
+    )]
+    TooNew(u32, String),
+    #[error("the library can't be read or written: {0}")]
+    Io(String),
+    #[cfg(feature = "sqlite")]
+    #[error("the library database failed: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+/// Where states that can't be read are backed up, next to eframe's own storage.
+fn backups_dir() -> Option<PathBuf> {
+    eframe::storage_dir(APP_ID).map(|dir| dir.join("backups"))
+}
+
+/// Copies `raw` into `dir`, so a state that can't be read isn't lost once the
+/// app saves over it. Returns where it went.
+fn back_up(dir: Option<&Path>, raw: &str, extension: &str) -> String {
+    let Some(dir) = dir else {
+        return "nowhere, there's no data directory".to_owned();
+    };
+    let stamp = Utc::now().format("%Y%m%dT%H%M%S");
+    let written = (|| -> io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        // Backups made within the same second are numbered rather than
+        // written over each other.
+        let mut n = 0;
+        loop {
+            let file = match n {
+                0 => dir.join(format!("state-{stamp}.{extension}")),
+                n => dir.join(format!("state-{stamp}-{n}.{extension}")),
+            };
+            match fs::File::options().write(true).create_new(true).open(&file) {
+                Ok(mut out) => {
+                    out.write_all(raw.as_bytes())?;
+                    return Ok(file);
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => n += 1,
+                Err(err) => return Err(err),
+            }
+        }
+    })();
+    match written {
+        Ok(file) => file.display().to_string(),
+        Err(err) => format!("nowhere, writing the backup failed: {err}"),
+    }
+}
+
+/// Reads the stored state, migrating it from older versions. `Ok(None)` on
+/// the first run.
+pub fn load_state(storage: &dyn eframe::Storage) -> Result<Option<ToStore>, StoreError> {
+    load_backing_up_to(storage, backups_dir().as_deref())
+}
+
+/// [`load_state`], backing states that can't be read up to `backups`.
+fn load_backing_up_to(
+    storage: &dyn eframe::Storage,
+    backups: Option<&Path>,
+) -> Result<Option<ToStore>, StoreError> {
+    let (raw, extension) = match storage.get_string(STATE_KEY) {
+        Some(raw) => (raw, "json"),
+        None => match storage.get_string(eframe::APP_KEY) {
+            Some(raw) => (raw, "ron"),
+            None => return Ok(None),
+        },
+    };
+    let unreadable = |reason: String| StoreError::Unreadable {
+        reason,
+        backup: back_up(backups, &raw, extension),
+    };
+    let (version, mut state) = if extension == "ron" {
+        let unversioned: Unversioned =
+            ron::from_str(&raw).map_err(|err| unreadable(err.to_string()))?;
+        let state = serde_json::to_value(unversioned).map_err(|err| unreadable(err.to_string()))?;
+        (0, state)
+    } else {
+        let stored: Versioned<Value> =
+            serde_json::from_str(&raw).map_err(|err| unreadable(err.to_string()))?;
+        (stored.version, stored.state)
+    };
+    if version > STORE_VERSION {
+        return Err(StoreError::TooNew(
+            version,
+            back_up(backups, &raw, extension),
+        ));
+    }
+    for migrate in &MIGRATIONS[version as usize..] {
+        migrate(&mut state);
+    }
+    serde_json::from_value(state)
+        .map(Some)
+        .map_err(|err| unreadable(err.to_string()))
+}
+
+/// The file eframe keeps its storage in, `app.ron` in the data directory, so
+/// the state can be read and written without a window. Writing it while the
+/// app is open loses whichever side saves first.
+pub struct FileStorage {
+    path: PathBuf,
+    kv: HashMap<String, String>,
+}
+
+impl FileStorage {
+    /// Reads the file at `path`, an empty storage if there's none yet.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, StoreError> {
+        let path = path.into();
+        let kv = match fs::read_to_string(&path) {
+            Ok(raw) => ron::from_str(&raw).map_err(|err| StoreError::Unreadable {
+                reason: err.to_string(),
+                backup: back_up(backups_dir().as_deref(), &raw, "ron"),
+            })?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(StoreError::Io(err.to_string())),
+        };
+        Ok(Self { path, kv })
+    }
+
+    /// The storage of the app, if the platform has a data directory.
+    pub fn in_data_dir() -> Result<Self, StoreError> {
+        let dir = eframe::storage_dir(APP_ID)
+            .ok_or_else(|| StoreError::Io("there's no data directory".to_owned()))?;
+        Self::open(dir.join("app.ron"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes the storage back, through a temporary file so the app never
+    /// reads half of it.
+    pub fn write(&self) -> Result<(), StoreError> {
+        let written = (|| -> io::Result<()> {
+            if let Some(parent) = self.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let ron = ron::ser::to_string_pretty(&self.kv, Default::default())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let tmp = self
+                .path
+                .with_extension(format!("tmp{}", std::process::id()));
+            fs::write(&tmp, ron)?;
+            fs::rename(tmp, &self.path)
+        })();
+        written.map_err(|err| StoreError::Io(err.to_string()))
+    }
+}
+
+impl eframe::Storage for FileStorage {
+    fn get_string(&self, key: &str) -> Option<String> {
+        self.kv.get(key).cloned()
+    }
+
+    fn set_string(&mut self, key: &str, value: String) {
+        self.kv.insert(key.to_owned(), value);
+    }
+
+    /// Writing can fail, which `flush` can't tell, so it's left to [`FileStorage::write`].
+    fn flush(&mut self) {}
+}
+
+pub fn save_state(storage: &mut dyn eframe::Storage, state: &ToStore) {
+    let versioned = Versioned {
+        version: STORE_VERSION,
+        state,
+    };
+    // Only fails for maps with non-string keys, which `ToStore` has none of.
+    if let Ok(json) = serde_json::to_string(&versioned) {
+        storage.set_string(STATE_KEY, json);
+    }
+}
+
+/// Where the app keeps its state. eframe's storage is always handed in, for
+/// whatever a store doesn't keep itself.
+pub trait StateStore {
+    /// The stored state, `Ok(None)` on the first run.
+    fn load(&mut self, storage: &dyn eframe::Storage) -> Result<Option<ToStore>, StoreError>;
+    fn save(&mut self, storage: &mut dyn eframe::Storage, state: ToStore)
+        -> Result<(), StoreError>;
+
+    /// What went wrong in the last load without failing it, e.g. state that
+    /// had to be reset while the library could be read.
+    fn take_warning(&mut self) -> Option<StoreError> {
+        None
+    }
+}
+
+/// Keeps everything in eframe's storage.
+pub struct EframeStore;
+
+impl StateStore for EframeStore {
+    fn load(&mut self, storage: &dyn eframe::Storage) -> Result<Option<ToStore>, StoreError> {
+        load_state(storage)
+    }
+
+    fn save(
+        &mut self,
+        storage: &mut dyn eframe::Storage,
+        state: ToStore,
+    ) -> Result<(), StoreError> {
+        save_state(storage, &state);
+        Ok(())
+    }
+}
+
+/// The store the app was built with: the SQLite database in the data
+/// directory with the `sqlite` feature, eframe's storage otherwise.
+pub fn open_store() -> Result<Box<dyn StateStore>, StoreError> {
+    #[cfg(feature = "sqlite")]
+    {
+        let dir = eframe::storage_dir(APP_ID)
+            .ok_or_else(|| StoreError::Io("there's no data directory".to_owned()))?;
+        let store = crate::sqlite::SqliteStore::open(&dir.join("library.sqlite3"))?;
+        Ok(Box::new(store))
+    }
+    #[cfg(not(feature = "sqlite"))]
+    Ok(Box::new(EframeStore))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TitleId;
+
+    /// An empty storage, and a directory for its backups.
+    fn storage(name: &str) -> (FileStorage, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("meepls-store-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let storage = FileStorage::open(dir.join("app.ron")).unwrap();
+        (storage, dir.join("backups"))
+    }
+
+    #[test]
+    fn migrates_the_unversioned_state() {
+        let (mut storage, backups) = storage("v0");
+        // As the first versions stored it, titles by display title.
+        let raw = r#"(selected_anchor:Recommend,titles:{"Heat (1995)":(id:"tt0113277",title:"Heat",year:1995,ty:Movie,rating:7.9,my_rating:Some(9.0),directors:["Michael Mann"],actors:[],genres:["Crime"],poster_img:"",description:"")},recommend:())"#;
+        eframe::Storage::set_string(&mut storage, eframe::APP_KEY, raw.to_owned());
+
+        let state = load_backing_up_to(&storage, Some(&backups))
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.selected_anchor, Anchor::Recommend);
+        let ids: Vec<TitleId> = state.titles.keys().copied().collect();
+        assert_eq!(ids, [TitleId(113277)]);
+        assert_eq!(state.titles[&TitleId(113277)].my_rating, Some(9.0));
+        assert!(!backups.exists());
+
+        // Saved again, it's read as the current version.
+        save_state(&mut storage, &state);
+        let reloaded = load_backing_up_to(&storage, Some(&backups))
+            .unwrap()
+            .unwrap();
+        assert_eq!(reloaded.titles, state.titles);
+    }
+
+    #[test]
+    fn backs_up_states_from_newer_versions() {
+        let (mut storage, backups) = storage("too-new");
+        let raw = format!(r#"{{"version":{},"state":{{}}}}"#, STORE_VERSION + 1);
+        eframe::Storage::set_string(&mut storage, STATE_KEY, raw.clone());
+
+        match load_backing_up_to(&storage, Some(&backups)) {
+            Err(StoreError::TooNew(version, backup)) => {
+                assert_eq!(version, STORE_VERSION + 1);
+                assert_eq!(fs::read_to_string(backup).unwrap(), raw);
+            }
+            other => panic!("expected TooNew, got {other:?}"),
+        }
+        fs::remove_dir_all(backups.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn backs_up_corrupt_states_without_writing_over_them() {
+        let (mut storage, backups) = storage("corrupt");
+        let raw = r#"{"version":1,"state":{"titles":"#;
+        eframe::Storage::set_string(&mut storage, STATE_KEY, raw.to_owned());
+
+        let backup = || match load_backing_up_to(&storage, Some(&backups)) {
+            Err(StoreError::Unreadable { backup, .. }) => backup,
+            other => panic!("expected Unreadable, got {other:?}"),
+        };
+        let (first, second) = (backup(), backup());
+        assert_ne!(first, second);
+        assert_eq!(fs::read_to_string(first).unwrap(), raw);
+        assert_eq!(fs::read_to_string(second).unwrap(), raw);
+        assert_eq!(
+            eframe::Storage::get_string(&storage, STATE_KEY).as_deref(),
+            Some(raw)
+        );
+        fs::remove_dir_all(backups.parent().unwrap()).unwrap();
+    }
+}
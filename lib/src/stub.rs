@@ -0,0 +1,164 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::provider::fixture_path;
+
+/// TMDB answers recorded for the tests.
+#[cfg(test)]
+pub(crate) const TEST_FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tmdb");
+
+const NOT_FOUND_BODY: &str = r#"{"success":false,"status_code":34,"status_message":"The resource you requested could not be found."}"#;
+
+/// An answer the stub gives instead of the fixture, to see how clients cope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// 429, with a `Retry-After` of this many seconds if set.
+    TooManyRequests { retry_after: Option<u32> },
+    /// A 5xx status.
+    ServerError(u16),
+    /// No answer, the connection is closed once the request is read.
+    Disconnect,
+}
+
+impl Fault {
+    /// `None` for faults that don't answer at all.
+    fn status_line(self) -> Option<String> {
+        match self {
+            Fault::TooManyRequests { .. } => Some("429 Too Many Requests".to_owned()),
+            Fault::ServerError(503) => Some("503 Service Unavailable".to_owned()),
+            Fault::ServerError(status) => Some(format!("{status} Internal Server Error")),
+            Fault::Disconnect => None,
+        }
+    }
+}
+
+/// A local HTTP server answering TMDB API requests from a fixture directory, so
+/// a `TmdbProvider` can be pointed at it with `with_base_url(server.url())`.
+/// It stops when dropped.
+pub struct StubServer {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+    faults: Arc<Mutex<VecDeque<Fault>>>,
+    requests: Arc<AtomicUsize>,
+}
+
+impl StubServer {
+    /// Starts serving `dir` on a free port of the loopback interface.
+    pub fn start(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let faults = Arc::new(Mutex::new(VecDeque::new()));
+        let requests = Arc::new(AtomicUsize::new(0));
+        let handle = {
+            let shutdown = Arc::clone(&shutdown);
+            let faults = Arc::clone(&faults);
+            let requests = Arc::clone(&requests);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shutdown.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    if let Ok(stream) = stream {
+                        requests.fetch_add(1, Ordering::Relaxed);
+                        let fault = faults.lock().unwrap().pop_front();
+                        // A broken connection only fails that one request.
+                        let _ = serve(&dir, fault, stream);
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            addr,
+            shutdown,
+            handle: Some(handle),
+            faults,
+            requests,
+        })
+    }
+
+    /// Answers the next requests with `faults`, one each, before serving the
+    /// fixtures again.
+    pub fn inject(&self, faults: impl IntoIterator<Item = Fault>) {
+        self.faults.lock().unwrap().extend(faults);
+    }
+
+    /// Number of requests received so far.
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Base URL to hand to the provider, in place of `https://api.themoviedb.org/3`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+}
+
+impl Drop for StubServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        // Wake the accept loop up so it notices.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve(dir: &Path, fault: Option<Fault>, mut stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers, nothing in them changes the answer.
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    if let Some(fault) = fault {
+        let Some(status_line) = fault.status_line() else {
+            // Dropping the stream closes the connection.
+            return Ok(());
+        };
+        let retry_after = match fault {
+            Fault::TooManyRequests {
+                retry_after: Some(secs),
+            } => format!("Retry-After: {secs}\r\n"),
+            _ => String::new(),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {status_line}\r\n{retry_after}Content-Length: 0\r\nConnection: close\r\n\r\n"
+        )?;
+        return stream.flush();
+    }
+
+    let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let search = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("query="));
+    let (status, body) = match fs::read(dir.join(fixture_path(path, search))) {
+        Ok(body) => ("200 OK", body),
+        Err(_) => ("404 Not Found", NOT_FOUND_BODY.as_bytes().to_vec()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
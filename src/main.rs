@@ -1,7 +1,4 @@
-use std::{
-    collections::BTreeMap,
-    sync::{Arc, Mutex},
-};
+use std::sync::{Arc, Mutex};
 
 #[cfg(feature = "reload")]
 use hot_lib::*;
@@ -11,7 +8,10 @@ use lib::*;
 #[cfg(feature = "reload")]
 #[hot_lib_reloader::hot_module(dylib = "lib")]
 mod hot_lib {
-    pub use lib::{Anchor, MyTitlesApp, RecommendApp, State, ToStore};
+    pub use lib::{
+        open_store, Anchor, DiskCache, EframeStore, MyTitlesApp, PosterLoader, RecommendApp,
+        Settings, SettingsApp, State, StateStore, ToStore, APP_ID,
+    };
 
     hot_functions_from_file!("lib/src/lib.rs");
 
@@ -21,6 +21,7 @@ mod hot_lib {
 
 pub struct MeeplsApp {
     state: State,
+    store: Box<dyn StateStore>,
 }
 
 impl MeeplsApp {
@@ -31,27 +32,52 @@ impl MeeplsApp {
 
         egui_extras::install_image_loaders(&cc.egui_ctx);
         let toasts = Arc::new(Mutex::new(egui_notify::Toasts::default()));
+        let settings = Arc::new(Mutex::new(Settings::default()));
+        // Added last so it's asked before the plain HTTP loader.
+        if let Some(cache) = DiskCache::in_data_dir() {
+            cc.egui_ctx
+                .add_bytes_loader(Arc::new(PosterLoader::new(cache, Arc::clone(&settings))));
+        }
         let state = State {
             selected_anchor: Anchor::default(),
-            my_titles: MyTitlesApp {
-                titles: BTreeMap::new(),
-                input_id: String::new(),
-                toasts: Arc::clone(&toasts),
-            },
+            my_titles: MyTitlesApp::new(Arc::clone(&toasts), Arc::clone(&settings)),
             recommend: RecommendApp::default(),
+            settings: SettingsApp::new(Arc::clone(&settings)),
             toasts,
         };
 
-        let mut slf = Self { state };
+        // Stays up until dismissed, it may be the only trace of the old library.
+        let toasts = Arc::clone(&state.toasts);
+        let sticky_error = |message: String| {
+            toasts.lock().unwrap().error(message).set_duration(None);
+        };
+        let store = open_store().unwrap_or_else(|err| {
+            sticky_error(format!("Keeping the library in the app's storage: {err}"));
+            Box::new(EframeStore)
+        });
+
+        let mut slf = Self { state, store };
         // Load previous app state (if any).
         // Note that you must enable the `persistence` feature for this to work.
         if let Some(storage) = cc.storage {
-            if let Some(stored) = eframe::get_value::<ToStore>(storage, eframe::APP_KEY) {
-                slf.state.selected_anchor = stored.selected_anchor;
-                slf.state.my_titles.titles = stored.titles;
-                slf.state.recommend = stored.recommend;
+            match slf.store.load(storage) {
+                Ok(Some(stored)) => {
+                    slf.state.selected_anchor = stored.selected_anchor;
+                    slf.state.my_titles.titles = stored.titles;
+                    slf.state.my_titles.lists = stored.lists;
+                    slf.state.my_titles.filter = stored.filter;
+                    slf.state.my_titles.queued = stored.queued;
+                    slf.state.recommend = stored.recommend;
+                    *settings.lock().unwrap() = stored.settings;
+                }
+                Ok(None) => {}
+                Err(err) => sticky_error(format!("Starting with an empty library: {err}")),
+            }
+            if let Some(err) = slf.store.take_warning() {
+                sticky_error(format!("Starting with default settings: {err}"));
             }
         }
+        slf.state.recommend.settings = settings;
 
         slf
     }
@@ -63,9 +89,19 @@ impl eframe::App for MeeplsApp {
         let to_store = ToStore {
             selected_anchor: self.state.selected_anchor,
             titles: self.state.my_titles.titles.clone(),
-            recommend: self.state.recommend,
+            lists: self.state.my_titles.lists.clone(),
+            filter: self.state.my_titles.filter.clone(),
+            queued: self.state.my_titles.queued.clone(),
+            recommend: self.state.recommend.to_store(),
+            settings: self.state.settings.settings.lock().unwrap().clone(),
         };
-        eframe::set_value(storage, eframe::APP_KEY, &to_store);
+        if let Err(err) = self.store.save(storage, to_store) {
+            self.state
+                .toasts
+                .lock()
+                .unwrap()
+                .error(format!("Saving the library failed: {err}"));
+        }
     }
 
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
@@ -83,7 +119,7 @@ fn main() -> eframe::Result<()> {
         ..Default::default()
     };
     eframe::run_native(
-        "meepls",
+        APP_ID,
         native_options,
         Box::new(|cc| {
             #[cfg(feature = "reload")]